@@ -0,0 +1,81 @@
+use bevy::{app::ScheduleRunnerPlugin, prelude::*};
+use bevy_flock_simulation_engine::{
+    boid_simulation::{components::*, spatial_index::*},
+    helpers::*,
+    HeadlessFlockPlugin,
+};
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+// `cargo run --release --example headless -- --medir` mide cuánto tarda cada índice espacial en
+// reconstruirse con 100 000 boids en vez de ejecutar la simulación
+fn main() {
+    if std::env::args().any(|arg| arg == "--medir") {
+        measure_spatial_indices(100_000, 60);
+        return;
+    }
+    App::new()
+        .add_plugins(
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / 60.0,
+            ))),
+        )
+        .add_plugins(HeadlessFlockPlugin)
+        .add_systems(Update, report)
+        .run();
+}
+
+fn report(
+    boids: Query<&Transform, With<Boid>>,
+    time: Res<Time>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if time.elapsed_secs() < 5.0 {
+        return;
+    }
+    let centre = boids
+        .iter()
+        .map(|transform| transform.translation.xy())
+        .sum::<Vec2>()
+        / boids.iter().len().max(1) as f32;
+    println!("{} boids, centro de masas en {centre}", boids.iter().len());
+    app_exit.write(AppExit::Success);
+}
+
+fn measure_spatial_indices(boid_count: u32, ticks: u32) {
+    let settings = SpatialIndexSettings {
+        bounds: Rect::from_center_size(Vec2::ZERO, Vec2::splat(20_000.0)),
+        cell_size: 100.0,
+        ..default()
+    };
+    let mut rng = rand::rng();
+    let mut boids = (0..boid_count)
+        .map(|i| {
+            let position = Vec2::new(
+                rng.random_range(settings.bounds.min.x..settings.bounds.max.x),
+                rng.random_range(settings.bounds.min.y..settings.bounds.max.y),
+            );
+            let velocity = Vec2::from_angle(rng.random_range(0.0..std::f32::consts::TAU)) * 100.0;
+            SpatialGridBoid::new(Entity::from_raw(i), position, velocity, false)
+        })
+        .collect::<Vec<_>>();
+    let tick = 1.0 / ticks as f32;
+    for kind in SpatialIndexKind::ALL {
+        let mut index = kind.create(&settings);
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..ticks {
+            for boid in &mut boids {
+                boid.position = settings.correct_position(boid.position + boid.velocity * tick);
+            }
+            let start = Instant::now();
+            index.rebuild(&boids);
+            elapsed += start.elapsed();
+        }
+        let per_tick = elapsed / ticks;
+        println!(
+            "{}: {boid_count} boids en {per_tick:?} por reconstrucción ({:.1} % de un tick a 60 Hz)",
+            kind.name(),
+            per_tick.as_secs_f32() * 60.0 * 100.0
+        );
+    }
+}
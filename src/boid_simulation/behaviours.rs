@@ -0,0 +1,441 @@
+use super::resources::*;
+use bevy::{ecs::query::QueryData, prelude::*};
+use rand::Rng;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum SteeringTarget {
+    Point(Vec2),
+    Entity(Entity),
+}
+
+impl Default for SteeringTarget {
+    fn default() -> Self {
+        Self::Point(Vec2::ZERO)
+    }
+}
+
+#[derive(Component, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
+pub struct Seek {
+    pub target: SteeringTarget,
+    pub weight: f32,
+}
+
+impl Seek {
+    pub fn new(target: SteeringTarget, weight: f32) -> Self {
+        Self { target, weight }
+    }
+}
+
+#[derive(Component, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
+pub struct Flee {
+    pub target: SteeringTarget,
+    pub weight: f32,
+    // 0 means the target is fled from at any distance
+    pub panic_distance: f32,
+}
+
+impl Flee {
+    pub fn new(target: SteeringTarget, weight: f32, panic_distance: f32) -> Self {
+        Self {
+            target,
+            weight,
+            panic_distance,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
+pub struct Arrive {
+    pub target: SteeringTarget,
+    pub weight: f32,
+    pub slowing_radius: f32,
+}
+
+impl Arrive {
+    pub fn new(target: SteeringTarget, weight: f32, slowing_radius: f32) -> Self {
+        Self {
+            target,
+            weight,
+            slowing_radius,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct Pursue {
+    pub target: Entity,
+    pub weight: f32,
+    pub max_prediction: f32,
+}
+
+impl Pursue {
+    pub fn new(target: Entity, weight: f32, max_prediction: f32) -> Self {
+        Self {
+            target,
+            weight,
+            max_prediction,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct Evade {
+    pub target: Entity,
+    pub weight: f32,
+    pub max_prediction: f32,
+    pub panic_distance: f32,
+}
+
+impl Evade {
+    pub fn new(target: Entity, weight: f32, max_prediction: f32, panic_distance: f32) -> Self {
+        Self {
+            target,
+            weight,
+            max_prediction,
+            panic_distance,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct Wander {
+    pub weight: f32,
+    pub distance: f32,
+    pub radius: f32,
+    pub jitter: f32,
+    pub angle: f32,
+}
+
+impl Wander {
+    pub fn new(weight: f32, distance: f32, radius: f32, jitter: f32) -> Self {
+        Self {
+            weight,
+            distance,
+            radius,
+            jitter,
+            angle: 0.0,
+        }
+    }
+
+    pub fn advance(&mut self, rng: &mut impl Rng, delta_secs: f32) {
+        let jitter = self.jitter * delta_secs;
+        if jitter > 0.0 {
+            self.angle += rng.random_range(-jitter..=jitter);
+        }
+    }
+}
+
+impl Default for Wander {
+    fn default() -> Self {
+        Self::new(0.1, 100.0, 50.0, 4.0)
+    }
+}
+
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FollowPath {
+    pub path: CubicCurve<Vec2>,
+    pub weight: f32,
+    pub radius: f32,
+    pub look_ahead: f32,
+    #[reflect(ignore)]
+    samples: Vec<Vec2>,
+}
+
+impl FollowPath {
+    pub const RESOLUTION: usize = 100;
+
+    pub fn new(path: CubicCurve<Vec2>, weight: f32, radius: f32, look_ahead: f32) -> Self {
+        let mut follow_path = Self {
+            path,
+            weight,
+            radius,
+            look_ahead,
+            samples: Vec::new(),
+        };
+        follow_path.resample();
+        follow_path
+    }
+
+    pub fn samples(&self) -> &[Vec2] {
+        &self.samples
+    }
+
+    pub fn resample(&mut self) {
+        self.samples = self.path.iter_positions(Self::RESOLUTION).collect();
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct Containment {
+    pub rect: Rect,
+    pub weight: f32,
+    pub margin: f32,
+}
+
+impl Containment {
+    pub fn new(rect: Rect, weight: f32, margin: f32) -> Self {
+        Self {
+            rect,
+            weight,
+            margin,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct FollowLeader {
+    pub leader: Entity,
+    pub weight: f32,
+    pub distance_behind: f32,
+    pub leader_sight: f32,
+}
+
+impl FollowLeader {
+    pub fn new(leader: Entity, weight: f32, distance_behind: f32, leader_sight: f32) -> Self {
+        Self {
+            leader,
+            weight,
+            distance_behind,
+            leader_sight,
+        }
+    }
+}
+
+// Containment keeps the boid out of walls, so it competes for the force budget with separation
+pub const CONTAINMENT_PRIORITY: u32 = 0;
+
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub struct SteeringBehaviours {
+    pub seek: Option<&'static Seek>,
+    pub flee: Option<&'static Flee>,
+    pub arrive: Option<&'static Arrive>,
+    pub pursue: Option<&'static Pursue>,
+    pub evade: Option<&'static Evade>,
+    pub wander: Option<&'static mut Wander>,
+    pub follow_path: Option<&'static FollowPath>,
+    pub containment: Option<&'static Containment>,
+    pub follow_leader: Option<&'static FollowLeader>,
+}
+
+// Positions and velocities of everything a behaviour may target, sampled once per tick
+#[derive(Resource, Default)]
+pub struct SteeringTargets {
+    targets: HashMap<Entity, (Vec2, Vec2)>,
+}
+
+impl SteeringTargets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec2, velocity: Vec2) {
+        self.targets.insert(entity, (position, velocity));
+    }
+
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<(Vec2, Vec2)> {
+        self.targets.get(&entity).copied()
+    }
+
+    pub fn position(&self, target: SteeringTarget) -> Option<Vec2> {
+        match target {
+            SteeringTarget::Point(point) => Some(point),
+            SteeringTarget::Entity(entity) => self.get(entity).map(|(position, _)| position),
+        }
+    }
+}
+
+impl SteeringBehavioursItem<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.seek.is_none()
+            && self.flee.is_none()
+            && self.arrive.is_none()
+            && self.pursue.is_none()
+            && self.evade.is_none()
+            && self.wander.is_none()
+            && self.follow_path.is_none()
+            && self.containment.is_none()
+            && self.follow_leader.is_none()
+    }
+
+    pub fn advance(&mut self, delta_secs: f32) {
+        if let Some(wander) = &mut self.wander {
+            wander.advance(&mut rand::rng(), delta_secs);
+        }
+    }
+
+    // Yields weighted (priority, steering) pairs in the same per-tick velocity units as the rules
+    pub fn steer(
+        &self,
+        params: BoidRuleParametres,
+        config: &BoidConfiguration,
+        targets: &SteeringTargets,
+        mut emit: impl FnMut(u32, Vec2),
+    ) {
+        let steering = Steering { params, config };
+        if let Some(containment) = self.containment {
+            emit(
+                CONTAINMENT_PRIORITY,
+                containment.weight * steering.contain(containment),
+            );
+        }
+        let mut emit = |weight: f32, force: Vec2| emit(BoidRule::DEFAULT_PRIORITY, weight * force);
+        if let Some(seek) = self.seek {
+            if let Some(target) = targets.position(seek.target) {
+                emit(seek.weight, steering.seek(target));
+            }
+        }
+        if let Some(flee) = self.flee {
+            if let Some(target) = targets.position(flee.target) {
+                emit(flee.weight, steering.flee(target, flee.panic_distance));
+            }
+        }
+        if let Some(arrive) = self.arrive {
+            if let Some(target) = targets.position(arrive.target) {
+                emit(
+                    arrive.weight,
+                    steering.arrive(target, arrive.slowing_radius),
+                );
+            }
+        }
+        if let Some(pursue) = self.pursue {
+            if let Some((position, velocity)) = targets.get(pursue.target) {
+                let future = steering.predict(position, velocity, pursue.max_prediction);
+                emit(pursue.weight, steering.seek(future));
+            }
+        }
+        if let Some(evade) = self.evade {
+            if let Some((position, velocity)) = targets.get(evade.target) {
+                let future = steering.predict(position, velocity, evade.max_prediction);
+                emit(evade.weight, steering.flee(future, evade.panic_distance));
+            }
+        }
+        if let Some(wander) = &self.wander {
+            emit(wander.weight, steering.wander(wander));
+        }
+        if let Some(follow_path) = self.follow_path {
+            emit(follow_path.weight, steering.follow_path(follow_path));
+        }
+        if let Some(follow_leader) = self.follow_leader {
+            if let Some((position, velocity)) = targets.get(follow_leader.leader) {
+                emit(
+                    follow_leader.weight,
+                    steering.follow_leader(follow_leader, position, velocity),
+                );
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Steering<'a> {
+    params: BoidRuleParametres<'a>,
+    config: &'a BoidConfiguration,
+}
+
+impl Steering<'_> {
+    fn offset_to(&self, point: Vec2) -> Vec2 {
+        self.params
+            .spatial_index
+            .offset(self.params.position, point)
+    }
+
+    fn towards(&self, desired: Vec2) -> Vec2 {
+        desired - self.params.velocity
+    }
+
+    fn seek(&self, target: Vec2) -> Vec2 {
+        self.towards(self.offset_to(target).normalize_or_zero() * self.config.max_speed)
+    }
+
+    fn flee(&self, target: Vec2, panic_distance: f32) -> Vec2 {
+        let away = -self.offset_to(target);
+        if panic_distance > 0.0 && away.length_squared() > panic_distance * panic_distance {
+            return Vec2::ZERO;
+        }
+        self.towards(away.normalize_or_zero() * self.config.max_speed)
+    }
+
+    fn arrive(&self, target: Vec2, slowing_radius: f32) -> Vec2 {
+        let offset = self.offset_to(target);
+        let distance = offset.length();
+        let speed = if slowing_radius > 0.0 {
+            self.config.max_speed * (distance / slowing_radius).min(1.0)
+        } else {
+            self.config.max_speed
+        };
+        self.towards(offset.normalize_or_zero() * speed)
+    }
+
+    fn predict(&self, position: Vec2, velocity: Vec2, max_prediction: f32) -> Vec2 {
+        let distance = self.offset_to(position).length();
+        let time = (distance / self.config.max_speed.max(f32::EPSILON)).min(max_prediction);
+        position + velocity * time
+    }
+
+    fn wander(&self, wander: &Wander) -> Vec2 {
+        let heading = self.params.velocity.normalize_or(Vec2::X);
+        let circle_centre = self.params.position + heading * wander.distance;
+        let target = circle_centre + heading.rotate(Vec2::from_angle(wander.angle)) * wander.radius;
+        self.seek(target)
+    }
+
+    fn follow_path(&self, follow_path: &FollowPath) -> Vec2 {
+        let samples = follow_path.samples();
+        let future = self.params.position
+            + self.params.velocity.normalize_or_zero() * follow_path.look_ahead;
+        let Some((closest, distance_squared)) = samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| (i, sample.distance_squared(future)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            return Vec2::ZERO;
+        };
+        if distance_squared <= follow_path.radius * follow_path.radius
+            && closest + 1 < samples.len()
+        {
+            return Vec2::ZERO;
+        }
+        self.seek(samples[(closest + 1).min(samples.len() - 1)])
+    }
+
+    fn follow_leader(&self, follow_leader: &FollowLeader, position: Vec2, velocity: Vec2) -> Vec2 {
+        let heading = velocity.normalize_or_zero();
+        let behind = position - heading * follow_leader.distance_behind;
+        let ahead = position + heading * follow_leader.leader_sight;
+        let sight_squared = follow_leader.leader_sight * follow_leader.leader_sight;
+        let mut steering = self.arrive(behind, follow_leader.distance_behind);
+        // Step out of the leader's way instead of blocking it
+        if self.offset_to(ahead).length_squared() <= sight_squared
+            || self.offset_to(position).length_squared() <= sight_squared
+        {
+            steering += self.flee(position, 0.0);
+        }
+        steering
+    }
+
+    fn contain(&self, containment: &Containment) -> Vec2 {
+        let inner = containment.rect.inflate(-containment.margin);
+        let future =
+            self.params.position + self.params.velocity.normalize_or_zero() * containment.margin;
+        if inner.contains(future) {
+            return Vec2::ZERO;
+        }
+        self.seek(future.clamp(inner.min, inner.max))
+    }
+}
@@ -5,7 +5,8 @@ use bevy::prelude::*;
 pub struct BoidBundle {
     name: Name,
     boid: Boid,
-    sprite: Sprite,
+    steering_body: SteeringBody,
+    species: Species,
     transform: Transform,
 }
 
@@ -32,12 +33,13 @@ impl BoidBundleBuilder {
         self
     }
 
-    pub fn sprite(mut self, image: Handle<Image>, colour: Color) -> Self {
-        self.0.sprite = Sprite {
-            image,
-            color: colour,
-            ..default()
-        };
+    pub fn steering_body(mut self, mass: f32, max_force: f32, max_turn_rate: f32) -> Self {
+        self.0.steering_body = SteeringBody::new(mass, max_force, max_turn_rate);
+        self
+    }
+
+    pub fn species(mut self, species: Species) -> Self {
+        self.0.species = species;
         self
     }
 
@@ -80,3 +82,47 @@ impl ForceFieldBundle {
         }
     }
 }
+
+#[derive(Bundle)]
+pub struct ObstacleBundle {
+    name: Name,
+    obstacle: Obstacle,
+    transform: Transform,
+}
+
+impl ObstacleBundle {
+    pub fn new(shape: ObstacleShape, position: Vec2) -> Self {
+        Self {
+            name: Name::from("Obstáculo"),
+            obstacle: Obstacle::new(shape),
+            transform: Transform::from_translation(position.extend(0.0)),
+        }
+    }
+
+    pub fn with_velocity(mut self, velocity: Vec2) -> Self {
+        self.obstacle.velocity = velocity;
+        self
+    }
+}
+
+#[derive(Bundle)]
+pub struct RefugeBundle {
+    name: Name,
+    refuge: Refuge,
+    transform: Transform,
+}
+
+impl RefugeBundle {
+    pub fn new(shape: RefugeShape, position: Vec2) -> Self {
+        Self {
+            name: Name::from("Refugio"),
+            refuge: Refuge::new(shape),
+            transform: Transform::from_translation(position.extend(0.0)),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RefugePolicy) -> Self {
+        self.refuge.policy = policy;
+        self
+    }
+}
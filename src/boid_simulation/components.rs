@@ -1,5 +1,8 @@
 use super::resources::*;
 use bevy::prelude::*;
+use core::f32;
+use itertools::Itertools;
+use std::collections::HashMap;
 
 #[derive(Component, Clone, Copy, Default, Reflect)]
 #[reflect(Component)]
@@ -25,6 +28,72 @@ impl Boid {
     pub fn add_velocity(&mut self, velocity: Vec2, config: &BoidConfiguration) {
         self.set_velocity(self.velocity() + velocity, config);
     }
+
+    pub fn steer(
+        &mut self,
+        force: Vec2,
+        body: &SteeringBody,
+        config: &BoidConfiguration,
+        delta_secs: f32,
+    ) {
+        let velocity = self.velocity() + force / body.mass.max(f32::EPSILON) * delta_secs;
+        if velocity != Vec2::ZERO {
+            let max_turn = body.max_turn_rate * delta_secs;
+            let turn = Vec2::from_angle(self.angle)
+                .angle_to(velocity)
+                .clamp(-max_turn, max_turn);
+            self.angle = Vec2::from_angle(self.angle + turn).to_angle();
+        }
+        // Only the part of the new velocity along the heading the boid could turn to counts
+        let speed = velocity.dot(Vec2::from_angle(self.angle));
+        self.speed = speed.clamp(config.min_speed, config.max_speed);
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct SteeringBody {
+    pub mass: f32,
+    pub max_force: f32,
+    pub max_turn_rate: f32,
+}
+
+impl SteeringBody {
+    pub fn new(mass: f32, max_force: f32, max_turn_rate: f32) -> Self {
+        Self {
+            mass,
+            max_force,
+            max_turn_rate,
+        }
+    }
+
+    // Forces come sorted by priority; each group is granted what is left of the budget, and the
+    // first one that doesn't fit is scaled down and ends the allocation
+    pub fn allocate(&self, prioritised_forces: impl IntoIterator<Item = (u32, Vec2)>) -> Vec2 {
+        let mut total = Vec2::ZERO;
+        let mut remaining = self.max_force;
+        for (_, group) in &prioritised_forces
+            .into_iter()
+            .chunk_by(|(priority, _)| *priority)
+        {
+            let force = group.map(|(_, force)| force).sum::<Vec2>();
+            let magnitude = force.length();
+            if magnitude <= remaining {
+                total += force;
+                remaining -= magnitude;
+            } else {
+                total += force * (remaining / magnitude);
+                break;
+            }
+        }
+        total
+    }
+}
+
+impl Default for SteeringBody {
+    fn default() -> Self {
+        Self::new(1.0, 3000.0, f32::consts::TAU)
+    }
 }
 
 #[derive(Component, Clone, Copy, Reflect)]
@@ -46,8 +115,164 @@ impl Default for BoidTestingUnit {
 }
 
 #[derive(Component)]
+#[require(Predation, PredatorBehaviour)]
 pub struct BoidPredator;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum PredatorState {
+    #[default]
+    Patrol,
+    Stalk,
+    Attack,
+    Rest,
+}
+
+impl PredatorState {
+    pub const ALL: [Self; 4] = [Self::Patrol, Self::Stalk, Self::Attack, Self::Rest];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Patrol => "Patrulla",
+            Self::Stalk => "Acecho",
+            Self::Attack => "Ataque",
+            Self::Rest => "Descanso",
+        }
+    }
+
+    // Patrolling predators keep the colour of their species
+    pub fn colour(&self) -> Option<Color> {
+        match self {
+            Self::Patrol => None,
+            Self::Stalk => Some(Color::srgb(1.0, 0.6, 0.1)),
+            Self::Attack => Some(Color::srgb(1.0, 0.1, 0.8)),
+            Self::Rest => Some(Color::srgb(0.5, 0.5, 0.6)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct PredatorTarget {
+    pub entity: Entity,
+    pub position: Vec2,
+    pub species: usize,
+}
+
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
+pub struct PredatorBehaviour {
+    pub state: PredatorState,
+    pub time_in_state: f32,
+    pub target: Option<PredatorTarget>,
+    // Set by the pack: where to stalk towards and whether to wait for the others before attacking
+    pub waypoint: Option<Vec2>,
+    pub hold_attack: bool,
+}
+
+impl PredatorBehaviour {
+    pub fn enter(&mut self, state: PredatorState) {
+        self.state = state;
+        self.time_in_state = 0.0;
+    }
+}
+
+// A satiated predator neither hunts nor strikes; after a miss it only has to wait for the
+// strike cooldown
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
+pub struct Predation {
+    pub satiation: f32,
+    pub strike_cooldown: f32,
+    pub attempts: u32,
+    pub captures: u32,
+}
+
+impl Predation {
+    pub const MISS_COOLDOWN: f32 = 0.5;
+
+    pub fn is_satiated(&self) -> bool {
+        self.satiation > 0.0
+    }
+
+    pub fn can_strike(&self) -> bool {
+        !self.is_satiated() && self.strike_cooldown <= 0.0
+    }
+
+    pub fn tick(&mut self, delta_secs: f32) {
+        self.satiation = (self.satiation - delta_secs).max(0.0);
+        self.strike_cooldown = (self.strike_cooldown - delta_secs).max(0.0);
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct Species(pub usize);
+
+// Index into `PackConfiguration`
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct Pack(pub usize);
+
+// Per-boid values that take precedence over the global `BoidConfiguration`
+#[derive(Component, Debug, Clone, Default, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct BoidParams {
+    pub min_speed: Option<f32>,
+    pub max_speed: Option<f32>,
+    pub scale: Option<f32>,
+    scalar_parametres: HashMap<ParamId, f32>,
+}
+
+impl BoidParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, id: ParamId, value: f32) -> Self {
+        self.set(id, value);
+        self
+    }
+
+    pub fn with_min_speed(mut self, min_speed: f32) -> Self {
+        self.min_speed = Some(min_speed);
+        self
+    }
+
+    pub fn with_max_speed(mut self, max_speed: f32) -> Self {
+        self.max_speed = Some(max_speed);
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    pub fn get(&self, id: ParamId) -> Option<f32> {
+        self.scalar_parametres.get(&id).copied()
+    }
+
+    pub fn set(&mut self, id: ParamId, value: f32) {
+        self.scalar_parametres.insert(id, value);
+    }
+
+    pub fn clear(&mut self, id: ParamId) {
+        self.scalar_parametres.remove(&id);
+    }
+
+    pub fn scalar_parametres(&self) -> impl Iterator<Item = (ParamId, f32)> + '_ {
+        self.scalar_parametres
+            .iter()
+            .map(|(&id, &value)| (id, value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_speed.is_none()
+            && self.max_speed.is_none()
+            && self.scale.is_none()
+            && self.scalar_parametres.is_empty()
+    }
+}
+
 #[derive(Component, Clone, Reflect)]
 #[reflect(Component)]
 pub struct WindCurrent {
@@ -109,3 +334,213 @@ impl ForceField {
         Self { charge }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub enum ObstacleShape {
+    Circle { radius: f32 },
+    Box { half_size: Vec2 },
+    Polygon { vertices: Vec<Vec2> },
+    Wall { start: Vec2, end: Vec2 },
+}
+
+impl ObstacleShape {
+    // Signed distance to the surface (negative inside) and outward normal, both in local space
+    pub fn signed_distance(&self, point: Vec2) -> (f32, Vec2) {
+        match self {
+            Self::Circle { radius } => (point.length() - radius, point.normalize_or(Vec2::Y)),
+            Self::Box { half_size } => {
+                let q = point.abs() - *half_size;
+                let distance = q.max(Vec2::ZERO).length() + q.max_element().min(0.0);
+                let normal = if q.cmpgt(Vec2::ZERO).any() {
+                    (q.max(Vec2::ZERO) * point.signum()).normalize_or(Vec2::Y)
+                } else if q.x > q.y {
+                    Vec2::new(point.x.signum(), 0.0)
+                } else {
+                    Vec2::new(0.0, point.y.signum())
+                };
+                (distance, normal)
+            }
+            Self::Polygon { vertices } => polygon_signed_distance(vertices, point),
+            Self::Wall { start, end } => {
+                let closest = closest_on_segment(*start, *end, point);
+                (
+                    point.distance(closest),
+                    (point - closest).normalize_or((*end - *start).perp().normalize_or(Vec2::Y)),
+                )
+            }
+        }
+    }
+
+    // Normal on the side `from` started at, if the step to `to` went through a wall
+    pub fn crossed(&self, from: Vec2, to: Vec2) -> Option<Vec2> {
+        let Self::Wall { start, end } = self else {
+            return None;
+        };
+        let direction = *end - *start;
+        let (before, after) = (
+            direction.perp_dot(from - *start),
+            direction.perp_dot(to - *start),
+        );
+        if before == 0.0 || before.signum() == after.signum() {
+            return None;
+        }
+        let crossing = from + (to - from) * (before / (before - after));
+        let along =
+            (crossing - *start).dot(direction) / direction.length_squared().max(f32::EPSILON);
+        (0.0..=1.0)
+            .contains(&along)
+            .then(|| direction.perp().normalize_or(Vec2::Y) * before.signum())
+    }
+}
+
+// Works for concave polygons too: inside is decided by counting edge crossings of a ray
+// towards +x
+fn polygon_signed_distance(vertices: &[Vec2], point: Vec2) -> (f32, Vec2) {
+    let mut closest = None::<(f32, Vec2)>;
+    let mut inside = false;
+    for (&a, &b) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+        let candidate = closest_on_segment(a, b, point);
+        let distance_squared = candidate.distance_squared(point);
+        if closest.is_none_or(|(best, _)| distance_squared < best) {
+            closest = Some((distance_squared, candidate));
+        }
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    let inside = inside && vertices.len() >= 3;
+    let Some((distance_squared, closest)) = closest else {
+        return (f32::MAX, Vec2::Y);
+    };
+    let distance = distance_squared.sqrt();
+    if inside {
+        (-distance, (closest - point).normalize_or(Vec2::Y))
+    } else {
+        (distance, (point - closest).normalize_or(Vec2::Y))
+    }
+}
+
+fn closest_on_segment(a: Vec2, b: Vec2, point: Vec2) -> Vec2 {
+    let ab = b - a;
+    let t = ((point - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct Obstacle {
+    pub shape: ObstacleShape,
+    // Kinematic obstacles drift with this velocity; static ones leave it at zero
+    pub velocity: Vec2,
+}
+
+impl Obstacle {
+    pub const BOID_RADIUS: f32 = 10.0;
+
+    pub fn new(shape: ObstacleShape) -> Self {
+        Self {
+            shape,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    pub fn kinematic(shape: ObstacleShape, velocity: Vec2) -> Self {
+        Self { shape, velocity }
+    }
+
+    // Boxes stay axis-aligned, every other shape turns with its transform
+    pub fn isometry(&self, transform: &Transform) -> Isometry2d {
+        let rotation = match self.shape {
+            ObstacleShape::Box { .. } => Rot2::IDENTITY,
+            _ => Rot2::radians(transform.rotation.to_euler(EulerRot::ZYX).0),
+        };
+        Isometry2d::new(transform.translation.xy(), rotation)
+    }
+
+    pub fn signed_distance(&self, isometry: Isometry2d, point: Vec2) -> (f32, Vec2) {
+        let (distance, normal) = self
+            .shape
+            .signed_distance(isometry.inverse_transform_point(point));
+        (distance, isometry.rotation * normal)
+    }
+
+    pub fn crossed(&self, isometry: Isometry2d, from: Vec2, to: Vec2) -> Option<Vec2> {
+        self.shape
+            .crossed(
+                isometry.inverse_transform_point(from),
+                isometry.inverse_transform_point(to),
+            )
+            .map(|normal| isometry.rotation * normal)
+    }
+}
+
+#[derive(Clone, Reflect)]
+pub enum RefugeShape {
+    Circle { radius: f32 },
+    Polygon { vertices: Vec<Vec2> },
+}
+
+impl RefugeShape {
+    // Same conventions as `ObstacleShape::signed_distance`
+    pub fn signed_distance(&self, point: Vec2) -> (f32, Vec2) {
+        match self {
+            Self::Circle { radius } => (point.length() - radius, point.normalize_or(Vec2::Y)),
+            Self::Polygon { vertices } => polygon_signed_distance(vertices, point),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum RefugePolicy {
+    #[default]
+    NoEntry,
+    NoCapture,
+}
+
+impl RefugePolicy {
+    pub const ALL: [Self; 2] = [Self::NoEntry, Self::NoCapture];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NoEntry => "Vedado a depredadores",
+            Self::NoCapture => "Sin capturas",
+        }
+    }
+}
+
+// Prey flee towards refuges and cannot be targeted or captured inside them
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct Refuge {
+    pub shape: RefugeShape,
+    pub policy: RefugePolicy,
+}
+
+impl Refuge {
+    pub fn new(shape: RefugeShape) -> Self {
+        Self {
+            shape,
+            policy: RefugePolicy::default(),
+        }
+    }
+
+    pub fn isometry(&self, transform: &Transform) -> Isometry2d {
+        Isometry2d::new(
+            transform.translation.xy(),
+            Rot2::radians(transform.rotation.to_euler(EulerRot::ZYX).0),
+        )
+    }
+
+    pub fn signed_distance(&self, isometry: Isometry2d, point: Vec2) -> (f32, Vec2) {
+        let (distance, normal) = self
+            .shape
+            .signed_distance(isometry.inverse_transform_point(point));
+        (distance, isometry.rotation * normal)
+    }
+
+    pub fn contains(&self, isometry: Isometry2d, point: Vec2) -> bool {
+        self.signed_distance(isometry, point).0 < 0.0
+    }
+}
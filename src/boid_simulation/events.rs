@@ -0,0 +1,14 @@
+use bevy::prelude::*;
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BoidOutOfGrid {
+    pub entity: Entity,
+    pub position: Vec2,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PreyCaptured {
+    pub predator: Entity,
+    pub prey: Entity,
+    pub position: Vec2,
+}
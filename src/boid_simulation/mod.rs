@@ -1,14 +1,35 @@
+pub mod behaviours;
 pub mod bundles;
 pub mod components;
+pub mod events;
 pub mod resources;
+pub mod rules;
+pub mod spatial_index;
 pub mod systems;
 
-use self::{components::*, resources::*, systems::*};
+use self::{behaviours::*, components::*, events::*, resources::*, spatial_index::*, systems::*};
 use crate::states::*;
 use bevy::prelude::*;
 
 #[derive(Default)]
-pub struct BoidSimulationPlugin;
+pub struct BoidSimulationPlugin {
+    pub headless: bool,
+    pub spatial_index: SpatialIndexKind,
+}
+
+impl BoidSimulationPlugin {
+    pub fn headless() -> Self {
+        Self {
+            headless: true,
+            ..default()
+        }
+    }
+
+    pub fn with_spatial_index(mut self, spatial_index: SpatialIndexKind) -> Self {
+        self.spatial_index = spatial_index;
+        self
+    }
+}
 
 impl Plugin for BoidSimulationPlugin {
     fn build(&self, app: &mut App) {
@@ -16,11 +37,40 @@ impl Plugin for BoidSimulationPlugin {
             .init_resource::<BoidRules>()
             .init_resource::<BoidConfiguration>()
             .init_resource::<SimulationConfiguration>()
-            .insert_resource(SpatialGrid::with_cell_size(200.0))
+            .init_resource::<WorldBounds>()
+            .init_resource::<SteeringTargets>()
+            .init_resource::<InteractionMatrix>()
+            .init_resource::<CaptureStatistics>()
+            .init_resource::<PackConfiguration>()
+            .insert_resource(BoidSpatialIndex::new(self.spatial_index))
+            .add_event::<BoidOutOfGrid>()
+            .add_event::<PreyCaptured>()
+            .register_type::<WorldBounds>()
             .register_type::<Boid>()
+            .register_type::<SteeringBody>()
+            .register_type::<Seek>()
+            .register_type::<Flee>()
+            .register_type::<Arrive>()
+            .register_type::<Pursue>()
+            .register_type::<Evade>()
+            .register_type::<Wander>()
+            .register_type::<FollowPath>()
+            .register_type::<Containment>()
+            .register_type::<FollowLeader>()
             .register_type::<WindCurrent>()
             .register_type::<ForceField>()
-            .add_systems(Startup, setup_boid_parametres)
+            .register_type::<Obstacle>()
+            .register_type::<Refuge>()
+            .register_type::<Species>()
+            .register_type::<Predation>()
+            .register_type::<PredatorBehaviour>()
+            .register_type::<Pack>()
+            .register_type::<PackConfiguration>()
+            .register_type::<CaptureStatistics>()
+            .register_type::<BoidParams>()
+            .register_type::<Personality>()
+            .register_type::<InteractionMatrix>()
+            .add_systems(Startup, setup_boid_rules)
             .add_systems(
                 PreUpdate,
                 (clear_simulation, setup_simulation)
@@ -29,8 +79,27 @@ impl Plugin for BoidSimulationPlugin {
             )
             .add_systems(
                 FixedUpdate,
-                (update_spatial_grid, update_boids, wrap_edges).chain(),
-            )
-            .add_systems(PostUpdate, draw_debug);
+                (
+                    follow_window_size,
+                    move_obstacles,
+                    update_spatial_index,
+                    update_predator_targets,
+                    coordinate_packs,
+                    update_predator_states,
+                    update_steering_targets,
+                    resample_paths,
+                    update_boids,
+                    resolve_obstacle_collisions,
+                    capture_prey,
+                    apply_boundaries,
+                )
+                    .chain(),
+            );
+        if !self.headless {
+            app.add_systems(
+                PostUpdate,
+                (colour_predators, draw_debug, draw_obstacles, draw_refuges),
+            );
+        }
     }
 }
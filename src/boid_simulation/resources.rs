@@ -1,16 +1,56 @@
+use super::{components::*, spatial_index::*};
 use crate::{constants::*, helpers::*};
-use bevy::prelude::*;
+use bevy::{math::FloatPow, prelude::*, tasks::TaskPool};
 use bevy_inspector_egui::prelude::*;
-use core::panic;
-use std::{collections::HashMap, fmt::Debug, ops::RangeInclusive};
+use itertools::{Either, Itertools};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    f32,
+    fmt::Debug,
+    ops::{Deref, DerefMut, RangeInclusive},
+};
 
-#[derive(Resource, Reflect, InspectorOptions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+pub struct ParamId(usize);
+
+impl ParamId {
+    pub const SEPARATION_RADIUS: Self = Self(0);
+    pub const VIEW_RADIUS: Self = Self(1);
+    pub const COHESION_WEIGHT: Self = Self(2);
+    pub const SEPARATION_WEIGHT: Self = Self(3);
+    pub const ALIGNMENT_WEIGHT: Self = Self(4);
+    pub const WIND_ANGLE: Self = Self(5);
+    pub const WIND_SPEED: Self = Self(6);
+    pub const FLEE_WEIGHT: Self = Self(7);
+    pub const VIEW_HALF_ANGLE: Self = Self(8);
+    pub const BLIND_SPOT_ANGLE: Self = Self(9);
+    pub const PERIPHERAL_FALLOFF: Self = Self(10);
+    pub const OBSTACLE_AVOIDANCE_WEIGHT: Self = Self(11);
+    pub const OBSTACLE_LOOK_AHEAD: Self = Self(12);
+    pub const BOLDNESS: Self = Self(13);
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Reflect)]
+pub struct ScalarParametre {
+    pub name: String,
+    pub value: f32,
+    pub range: RangeInclusive<f32>,
+}
+
+#[derive(Resource, Clone, Reflect, InspectorOptions)]
 #[reflect(Resource, InspectorOptions)]
 pub struct BoidConfiguration {
     pub min_speed: f32,
     pub max_speed: f32,
     pub scale: f32,
-    pub scalar_parametres: HashMap<String, (f32, RangeInclusive<f32>)>,
+    pub personality: Personality,
+    scalar_parametres: Vec<ScalarParametre>,
+    parametre_ids: HashMap<String, ParamId>,
 }
 
 impl BoidConfiguration {
@@ -38,101 +78,418 @@ impl BoidConfiguration {
         name: &str,
         value: f32,
         range: RangeInclusive<f32>,
-    ) -> &mut Self {
-        self.scalar_parametres
-            .insert(name.to_owned(), (value, range));
-        self
+    ) -> ParamId {
+        assert!(
+            !self.parametre_ids.contains_key(name),
+            "Ya existe el parámetro '{name}'"
+        );
+        let id = ParamId(self.scalar_parametres.len());
+        self.scalar_parametres.push(ScalarParametre {
+            name: name.to_owned(),
+            value,
+            range,
+        });
+        self.parametre_ids.insert(name.to_owned(), id);
+        id
+    }
+
+    pub fn scalar_parametre(&self, id: ParamId) -> f32 {
+        self.scalar_parametres[id.0].value
+    }
+
+    pub fn scalar_parametre_mut(&mut self, id: ParamId) -> &mut f32 {
+        &mut self.scalar_parametres[id.0].value
+    }
+
+    pub fn scalar_range(&self, id: ParamId) -> &RangeInclusive<f32> {
+        &self.scalar_parametres[id.0].range
+    }
+
+    pub fn lower_scalar_constant(&self, id: ParamId) -> f32 {
+        *self.scalar_range(id).start()
+    }
+
+    pub fn upper_scalar_constant(&self, id: ParamId) -> f32 {
+        *self.scalar_range(id).end()
+    }
+
+    pub fn parametre_name(&self, id: ParamId) -> &str {
+        &self.scalar_parametres[id.0].name
+    }
+
+    pub fn param_id(&self, name: &str) -> Option<ParamId> {
+        self.parametre_ids.get(name).copied()
     }
 
-    pub fn scalar_parametre(&self, name: &str) -> f32 {
-        *self
-            .get_scalar_parametre(name)
-            .expect(&format!("No existe el parámetro '{name}'"))
-            .0
+    pub fn try_scalar_parametre(&self, name: &str) -> Option<f32> {
+        self.param_id(name).map(|id| self.scalar_parametre(id))
     }
 
-    pub fn scalar_parametre_mut(&mut self, name: &str) -> &mut f32 {
-        &mut *self
-            .get_scalar_parametre_mut(name)
-            .expect(&format!("No existe el parámetro '{name}'"))
-            .0
+    pub fn try_scalar_parametre_mut(&mut self, name: &str) -> Option<&mut f32> {
+        self.param_id(name).map(|id| self.scalar_parametre_mut(id))
     }
 
-    pub fn lower_scalar_constant(&self, name: &str) -> f32 {
-        *self.get_scalar_range_constant(name).start()
+    pub fn scalar_parametres(&self) -> &[ScalarParametre] {
+        &self.scalar_parametres
     }
 
-    pub fn upper_scalar_constant(&self, name: &str) -> f32 {
-        *self.get_scalar_range_constant(name).end()
+    pub fn param_ids(&self) -> impl Iterator<Item = ParamId> {
+        (0..self.scalar_parametres.len()).map(ParamId)
     }
 
-    fn get_scalar_parametre(&self, name: &str) -> Option<(&f32, &RangeInclusive<f32>)> {
-        match self.scalar_parametres.get(name) {
-            Some((value, range)) => Some((value, range)),
-            None => None,
+    pub fn apply_overrides(&mut self, params: &BoidParams) {
+        let speed_range = Self::SPEED_RANGE;
+        if let Some(min_speed) = params.min_speed {
+            self.min_speed = min_speed.clamp(*speed_range.start(), *speed_range.end());
+        }
+        if let Some(max_speed) = params.max_speed {
+            self.max_speed = max_speed.clamp(*speed_range.start(), *speed_range.end());
         }
+        self.max_speed = self.max_speed.max(self.min_speed);
+        if let Some(scale) = params.scale {
+            self.scale = scale.clamp(*Self::SCALE_RANGE.start(), *Self::SCALE_RANGE.end());
+        }
+        for (id, value) in params.scalar_parametres() {
+            if let Some(parametre) = self.scalar_parametres.get_mut(id.0) {
+                parametre.value = value.clamp(*parametre.range.start(), *parametre.range.end());
+            }
+        }
+    }
+
+    pub fn overridden(&self, params: &BoidParams) -> Self {
+        let mut config = self.clone();
+        config.apply_overrides(params);
+        config
     }
 
-    fn get_scalar_parametre_mut(&mut self, name: &str) -> Option<(&mut f32, &RangeInclusive<f32>)> {
-        match self.scalar_parametres.get_mut(name) {
-            Some((value, range)) => Some((value, range)),
-            None => None,
+    // Hands back the configuration a boid sees. Overrides are written into `scratch`, which only
+    // copies values once it holds the same parametres, so it is cheap to reuse across boids
+    pub fn resolve<'a>(
+        &'a self,
+        params: Option<&BoidParams>,
+        scratch: &'a mut Option<Self>,
+    ) -> &'a Self {
+        let Some(params) = params else {
+            return self;
+        };
+        let config = scratch.get_or_insert_with(|| self.clone());
+        if config.scalar_parametres.len() == self.scalar_parametres.len() {
+            config.min_speed = self.min_speed;
+            config.max_speed = self.max_speed;
+            config.scale = self.scale;
+            for (parametre, global) in config
+                .scalar_parametres
+                .iter_mut()
+                .zip(&self.scalar_parametres)
+            {
+                parametre.value = global.value;
+                parametre.range = global.range.clone();
+            }
+        } else {
+            config.clone_from(self);
         }
+        config.apply_overrides(params);
+        config
+    }
+
+    pub fn perception_radius(&self) -> f32 {
+        self.scalar_parametre(ParamId::VIEW_RADIUS)
+            .max(self.scalar_parametre(ParamId::SEPARATION_RADIUS))
+    }
+
+    // Same as resolving the overrides and asking for the perception radius, without the copy
+    pub fn perception_radius_with(&self, params: &BoidParams) -> f32 {
+        [ParamId::VIEW_RADIUS, ParamId::SEPARATION_RADIUS]
+            .into_iter()
+            .map(|id| {
+                let parametre = &self.scalar_parametres[id.0];
+                params.get(id).map_or(parametre.value, |value| {
+                    value.clamp(*parametre.range.start(), *parametre.range.end())
+                })
+            })
+            .fold(0.0, f32::max)
     }
 
-    fn get_scalar_range_constant(&self, name: &str) -> RangeInclusive<f32> {
-        self.get_scalar_parametre(name)
-            .expect("No existe esto")
-            .1
-            .clone()
+    fn add_builtin_parametre(
+        &mut self,
+        id: ParamId,
+        name: &str,
+        value: f32,
+        range: RangeInclusive<f32>,
+    ) -> &mut Self {
+        let registered = self.add_scalar_parametre(name, value, range);
+        assert_eq!(
+            registered, id,
+            "El parámetro '{name}' no se ha registrado en su posición"
+        );
+        self
     }
 }
 
 impl Default for BoidConfiguration {
     fn default() -> Self {
-        Self {
+        let mut config = Self {
             min_speed: 100.0,
             max_speed: 300.0,
             scale: 1.0,
-            scalar_parametres: HashMap::new(),
-        }
+            personality: Personality::default(),
+            scalar_parametres: Vec::new(),
+            parametre_ids: HashMap::new(),
+        };
+        config
+            .add_builtin_parametre(
+                ParamId::SEPARATION_RADIUS,
+                "Radio de separación",
+                50.0,
+                1.0..=100.0,
+            )
+            .add_builtin_parametre(ParamId::VIEW_RADIUS, "Radio de visión", 100.0, 1.0..=200.0)
+            .add_builtin_parametre(
+                ParamId::COHESION_WEIGHT,
+                "Peso de cohesión",
+                0.25,
+                0.0..=1.0,
+            )
+            .add_builtin_parametre(
+                ParamId::SEPARATION_WEIGHT,
+                "Peso de separación",
+                1.0,
+                0.0..=5.0,
+            )
+            .add_builtin_parametre(
+                ParamId::ALIGNMENT_WEIGHT,
+                "Peso de alineamiento",
+                0.125,
+                0.0..=1.0,
+            )
+            .add_builtin_parametre(
+                ParamId::WIND_ANGLE,
+                "Ángulo del viento",
+                -120.0,
+                -180.0..=180.0,
+            )
+            .add_builtin_parametre(
+                ParamId::WIND_SPEED,
+                "Velocidad del viento",
+                100.0,
+                0.0..=(Self::highest_speed() * 2.0),
+            )
+            .add_builtin_parametre(ParamId::FLEE_WEIGHT, "Peso de huida", 0.5, 0.0..=1.0)
+            .add_builtin_parametre(
+                ParamId::VIEW_HALF_ANGLE,
+                "Semiángulo de visión",
+                180.0,
+                0.0..=180.0,
+            )
+            .add_builtin_parametre(
+                ParamId::BLIND_SPOT_ANGLE,
+                "Ángulo muerto trasero",
+                0.0,
+                0.0..=180.0,
+            )
+            .add_builtin_parametre(
+                ParamId::PERIPHERAL_FALLOFF,
+                "Atenuación periférica",
+                0.0,
+                0.0..=1.0,
+            )
+            .add_builtin_parametre(
+                ParamId::OBSTACLE_AVOIDANCE_WEIGHT,
+                "Peso de evasión de obstáculos",
+                0.5,
+                0.0..=1.0,
+            )
+            .add_builtin_parametre(
+                ParamId::OBSTACLE_LOOK_AHEAD,
+                "Anticipación de obstáculos",
+                80.0,
+                0.0..=300.0,
+            )
+            .add_builtin_parametre(ParamId::BOLDNESS, "Audacia", 0.5, 0.0..=1.0);
+        config
     }
 }
 
 impl IntoIterator for BoidConfiguration {
     type Item = (String, (f32, RangeInclusive<f32>));
-    type IntoIter = std::collections::hash_map::IntoIter<String, (f32, RangeInclusive<f32>)>;
+    type IntoIter =
+        std::iter::Map<std::vec::IntoIter<ScalarParametre>, fn(ScalarParametre) -> Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.scalar_parametres.into_iter()
+        self.scalar_parametres
+            .into_iter()
+            .map(|p| -> Self::Item { (p.name, (p.value, p.range)) })
     }
 }
 
 impl<'a> IntoIterator for &'a BoidConfiguration {
     type Item = (&'a String, (&'a f32, &'a RangeInclusive<f32>));
     type IntoIter = std::iter::Map<
-        std::collections::hash_map::Iter<'a, String, (f32, RangeInclusive<f32>)>,
-        fn((&'a String, &'a (f32, RangeInclusive<f32>))) -> Self::Item,
+        std::slice::Iter<'a, ScalarParametre>,
+        fn(&'a ScalarParametre) -> Self::Item,
     >;
 
     fn into_iter(self) -> Self::IntoIter {
         self.scalar_parametres
             .iter()
-            .map(|(k, v)| -> Self::Item { (k, (&v.0, &v.1)) })
+            .map(|p| -> Self::Item { (&p.name, (&p.value, &p.range)) })
     }
 }
 
 impl<'a> IntoIterator for &'a mut BoidConfiguration {
     type Item = (&'a String, (&'a mut f32, &'a RangeInclusive<f32>));
     type IntoIter = std::iter::Map<
-        std::collections::hash_map::IterMut<'a, String, (f32, RangeInclusive<f32>)>,
-        fn((&'a String, &'a mut (f32, RangeInclusive<f32>))) -> Self::Item,
+        std::slice::IterMut<'a, ScalarParametre>,
+        fn(&'a mut ScalarParametre) -> Self::Item,
     >;
 
     fn into_iter(self) -> Self::IntoIter {
         self.scalar_parametres
             .iter_mut()
-            .map(|(k, v)| -> Self::Item { (k, (&mut v.0, &v.1)) })
+            .map(|p| -> Self::Item { (&p.name, (&mut p.value, &p.range)) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum TraitDistribution {
+    Uniform { min: f32, max: f32 },
+    Normal { mean: f32, std_dev: f32 },
+    // `sigma` is the standard deviation of the logarithm, so the spread is relative to the median
+    LogNormal { median: f32, sigma: f32 },
+}
+
+impl TraitDistribution {
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        match *self {
+            Self::Uniform { min, max } => {
+                if min < max {
+                    rng.random_range(min..=max)
+                } else {
+                    min
+                }
+            }
+            Self::Normal { mean, std_dev } => mean + std_dev * standard_normal(rng),
+            Self::LogNormal { median, sigma } => median * (sigma * standard_normal(rng)).exp(),
+        }
+    }
+}
+
+// Box-Muller transform
+fn standard_normal(rng: &mut impl Rng) -> f32 {
+    let u1 = rng.random_range(f32::EPSILON..=1.0);
+    let u2 = rng.random::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (f32::consts::TAU * u2).cos()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum PersonalityTrait {
+    Parametre(ParamId),
+    MinSpeed,
+    MaxSpeed,
+    Scale,
+    // Speed the boid spawns at
+    PreferredSpeed,
+}
+
+#[derive(Debug, Clone, Default, Reflect)]
+pub struct Personality {
+    traits: Vec<(PersonalityTrait, TraitDistribution)>,
+}
+
+impl Personality {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A gentle spread around the defaults of `config`
+    pub fn heterogeneous(config: &BoidConfiguration) -> Self {
+        Self::new()
+            .with(
+                PersonalityTrait::Parametre(ParamId::BOLDNESS),
+                TraitDistribution::Normal {
+                    mean: config.scalar_parametre(ParamId::BOLDNESS),
+                    std_dev: 0.15,
+                },
+            )
+            .with(
+                PersonalityTrait::Parametre(ParamId::VIEW_RADIUS),
+                TraitDistribution::LogNormal {
+                    median: config.scalar_parametre(ParamId::VIEW_RADIUS),
+                    sigma: 0.2,
+                },
+            )
+            .with(
+                PersonalityTrait::PreferredSpeed,
+                TraitDistribution::Normal {
+                    mean: config.average_speed(),
+                    std_dev: (config.max_speed - config.min_speed) / 6.0,
+                },
+            )
+    }
+
+    pub fn with(
+        mut self,
+        personality_trait: PersonalityTrait,
+        distribution: TraitDistribution,
+    ) -> Self {
+        self.set(personality_trait, distribution);
+        self
+    }
+
+    pub fn set(&mut self, personality_trait: PersonalityTrait, distribution: TraitDistribution) {
+        match self
+            .traits
+            .iter_mut()
+            .find(|(existing, _)| *existing == personality_trait)
+        {
+            Some((_, existing)) => *existing = distribution,
+            None => self.traits.push((personality_trait, distribution)),
+        }
+    }
+
+    pub fn remove(&mut self, personality_trait: PersonalityTrait) {
+        self.traits
+            .retain(|(existing, _)| *existing != personality_trait);
+    }
+
+    pub fn traits(&self) -> &[(PersonalityTrait, TraitDistribution)] {
+        &self.traits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.traits.is_empty()
+    }
+
+    // Draws one boid's traits, clamped to the range of each parametre, and the speed it should
+    // spawn at
+    pub fn sample(&self, config: &BoidConfiguration, rng: &mut impl Rng) -> (BoidParams, f32) {
+        let clamp =
+            |value: f32, range: &RangeInclusive<f32>| value.clamp(*range.start(), *range.end());
+        let mut params = BoidParams::new();
+        let mut preferred_speed = None;
+        for (personality_trait, distribution) in &self.traits {
+            let value = distribution.sample(rng);
+            match *personality_trait {
+                PersonalityTrait::Parametre(id) => {
+                    params.set(id, clamp(value, config.scalar_range(id)))
+                }
+                PersonalityTrait::MinSpeed => {
+                    params.min_speed = Some(clamp(value, &BoidConfiguration::SPEED_RANGE))
+                }
+                PersonalityTrait::MaxSpeed => {
+                    params.max_speed = Some(clamp(value, &BoidConfiguration::SPEED_RANGE))
+                }
+                PersonalityTrait::Scale => {
+                    params.scale = Some(clamp(value, &BoidConfiguration::SCALE_RANGE))
+                }
+                PersonalityTrait::PreferredSpeed => preferred_speed = Some(value),
+            }
+        }
+        let resolved = config.overridden(&params);
+        let speed = preferred_speed.map_or(resolved.average_speed(), |speed| {
+            speed.clamp(resolved.min_speed, resolved.max_speed)
+        });
+        (params, speed)
     }
 }
 
@@ -143,10 +500,36 @@ pub struct SimulationConfiguration {
     pub normal_boids: u32,
     pub predators: u32,
     pub predator_hunt_weight: f32,
+    pub boundary_mode: BoundaryMode,
+    pub boundary_margin: f32,
+    pub boundary_turn_force: f32,
+    pub out_of_grid_policy: OutOfGridPolicy,
+    pub cell_size_policy: CellSizePolicy,
+    pub grid_cell_size: f32,
+    pub neighbourhood_mode: NeighbourhoodMode,
+    pub topological_neighbours: usize,
+    pub steering_model: SteeringModel,
+    pub integrator: Integrator,
+    pub substeps: u32,
+    pub capture_radius: f32,
+    pub capture_probability: f32,
+    pub handling_time: f32,
+    pub capture_outcome: CaptureOutcome,
+    pub predator_behaviour: PredatorBehaviourConfiguration,
+    pub pack_hunting: bool,
+    pub refuge_range: f32,
 }
 
 impl SimulationConfiguration {
     pub const BOIDS_RANGE: RangeInclusive<u32> = 0..=10000;
+    pub const BOUNDARY_MARGIN_RANGE: RangeInclusive<f32> = 0.0..=500.0;
+    pub const BOUNDARY_TURN_FORCE_RANGE: RangeInclusive<f32> = 0.0..=100.0;
+    pub const GRID_CELL_SIZE_RANGE: RangeInclusive<f32> = 10.0..=500.0;
+    pub const TOPOLOGICAL_NEIGHBOURS_RANGE: RangeInclusive<usize> = 1..=50;
+    pub const SUBSTEPS_RANGE: RangeInclusive<u32> = 1..=16;
+    pub const CAPTURE_RADIUS_RANGE: RangeInclusive<f32> = 1.0..=100.0;
+    pub const HANDLING_TIME_RANGE: RangeInclusive<f32> = 0.0..=30.0;
+    pub const REFUGE_RANGE_RANGE: RangeInclusive<f32> = 0.0..=1000.0;
 
     fn new(
         should_draw: bool,
@@ -159,6 +542,24 @@ impl SimulationConfiguration {
             normal_boids,
             predators,
             predator_hunt_weight,
+            boundary_mode: BoundaryMode::default(),
+            boundary_margin: 100.0,
+            boundary_turn_force: 20.0,
+            out_of_grid_policy: OutOfGridPolicy::default(),
+            cell_size_policy: CellSizePolicy::default(),
+            grid_cell_size: 200.0,
+            neighbourhood_mode: NeighbourhoodMode::default(),
+            topological_neighbours: 7,
+            steering_model: SteeringModel::default(),
+            integrator: Integrator::default(),
+            substeps: 1,
+            capture_radius: 15.0,
+            capture_probability: 0.3,
+            handling_time: 3.0,
+            capture_outcome: CaptureOutcome::default(),
+            predator_behaviour: PredatorBehaviourConfiguration::default(),
+            pack_hunting: false,
+            refuge_range: 300.0,
         }
     }
 
@@ -169,6 +570,18 @@ impl SimulationConfiguration {
     pub fn max_boids() -> u32 {
         *Self::BOIDS_RANGE.end()
     }
+
+    pub fn cell_size(&self, perception_radius: f32) -> f32 {
+        let cell_size = match self.cell_size_policy {
+            CellSizePolicy::PerceptionRadius => perception_radius,
+            CellSizePolicy::HalfPerceptionRadius => perception_radius / 2.0,
+            CellSizePolicy::Fixed => self.grid_cell_size,
+        };
+        cell_size.clamp(
+            *Self::GRID_CELL_SIZE_RANGE.start(),
+            *Self::GRID_CELL_SIZE_RANGE.end(),
+        )
+    }
 }
 
 impl Default for SimulationConfiguration {
@@ -177,258 +590,1532 @@ impl Default for SimulationConfiguration {
     }
 }
 
-pub struct BoidRuleParametres<'a> {
-    pub entity: Entity,
-    pub position: Vec2,
-    pub velocity: Vec2,
-    pub cell: &'a SpatialGridCell,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum BoundaryMode {
+    #[default]
+    Wrap,
+    Reflect,
+    SteerBack,
+    Despawn,
+    Respawn,
 }
 
-pub trait Rule: Fn(BoidRuleParametres, &BoidConfiguration) -> Vec2 + Send + Sync + 'static {}
-impl<T: Fn(BoidRuleParametres, &BoidConfiguration) -> Vec2 + Send + Sync + 'static> Rule for T {}
-
-#[derive(Resource, Default)]
-pub struct BoidRules(Vec<Box<dyn Rule>>);
+impl BoundaryMode {
+    pub const ALL: [Self; 5] = [
+        Self::Wrap,
+        Self::Reflect,
+        Self::SteerBack,
+        Self::Despawn,
+        Self::Respawn,
+    ];
 
-impl BoidRules {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wrap => "Toroidal",
+            Self::Reflect => "Paredes",
+            Self::SteerBack => "Margen de retorno",
+            Self::Despawn => "Abierto (eliminar)",
+            Self::Respawn => "Abierto (reaparecer)",
+        }
     }
+}
 
-    pub fn add(&mut self, rule: impl Rule) -> &mut Self {
-        self.0.push(Box::new(rule));
-        self
-    }
+// Patrol until prey is within `detection_radius`, stalk it until it is within `attack_distance`,
+// sprint for at most `attack_time` and rest afterwards. A stalk that runs out of time also ends
+// in a rest
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct PredatorBehaviourConfiguration {
+    pub detection_radius: f32,
+    pub attack_distance: f32,
+    pub stalk_time: f32,
+    pub attack_time: f32,
+    pub rest_time: f32,
+    pub stalk_intensity: f32,
+    pub sprint_multiplier: f32,
+    pub targeting: TargetingStrategy,
+    pub isolation_radius: f32,
+    pub confusion_strength: f32,
 }
 
-impl<'a> IntoIterator for &'a BoidRules {
-    type Item = &'a Box<dyn Rule>;
-    type IntoIter = std::slice::Iter<'a, Box<dyn Rule>>;
+impl PredatorBehaviourConfiguration {
+    pub const DISTANCE_RANGE: RangeInclusive<f32> = 0.0..=500.0;
+    pub const DURATION_RANGE: RangeInclusive<f32> = 0.0..=30.0;
+    pub const SPRINT_MULTIPLIER_RANGE: RangeInclusive<f32> = 1.0..=3.0;
+    pub const CONFUSION_STRENGTH_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+    // Locks and losses per second when there is no confusion at all, or a total one
+    pub const TARGET_LOCK_RATE: f32 = 4.0;
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.iter()
+    // Chance of keeping a lock on prey with `prey_nearby` other prey around it
+    pub fn lock_probability(&self, prey_nearby: usize) -> f32 {
+        1.0 / (1.0 + self.confusion_strength * prey_nearby as f32)
     }
 }
 
-#[derive(Resource, Reflect, InspectorOptions)]
-#[reflect(Resource, InspectorOptions)]
-pub struct SpatialGrid {
-    cells: Cells,
-    rows: u32,
-    columns: u32,
+impl Default for PredatorBehaviourConfiguration {
+    fn default() -> Self {
+        Self {
+            detection_radius: 150.0,
+            attack_distance: 60.0,
+            stalk_time: 6.0,
+            attack_time: 2.0,
+            rest_time: 3.0,
+            stalk_intensity: 0.5,
+            sprint_multiplier: 1.5,
+            targeting: TargetingStrategy::default(),
+            isolation_radius: 50.0,
+            confusion_strength: 0.2,
+        }
+    }
 }
 
-impl SpatialGrid {
-    pub fn new(rows: u32, columns: u32, cell_size: f32) -> Self {
-        assert!(
-            rows > 0 && columns > 0,
-            "Prohibido crear un SpatialGrid unidimensional o nildimensional o de dimensiones negativas"
-        );
-        assert!(
-            cell_size > 1.0,
-            "Es inútil trabajar con celdas más pequeñas que un píxel"
-        );
-        let mut cells = Vec::new();
-        let offset = Vec2::new((columns - 1) as f32, (rows - 1) as f32) / 2.0;
-        for r in 0..rows {
-            for c in 0..columns {
-                cells.push(SpatialGridCell::new(
-                    r,
-                    c,
-                    cell_size,
-                    (Vec2::new(c as f32, r as f32) - offset) * cell_size,
-                ));
-            }
-        }
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub struct PackSettings {
+    pub name: String,
+    pub share_targets: bool,
+    pub encircle: bool,
+    pub encircle_radius: f32,
+    pub coordinated_attack: bool,
+    // Fraction of the stalking members that must be in position before all of them attack
+    pub attack_quorum: f32,
+}
+
+impl PackSettings {
+    pub const ENCIRCLE_RADIUS_RANGE: RangeInclusive<f32> = 10.0..=400.0;
+    // How far from its slot around the prey a member still counts as in position, relative to
+    // the encircling radius
+    pub const SLOT_TOLERANCE: f32 = 0.5;
+
+    pub fn new(name: &str) -> Self {
         Self {
-            cells,
-            rows,
-            columns,
+            name: name.to_owned(),
+            share_targets: true,
+            encircle: true,
+            encircle_radius: 120.0,
+            coordinated_attack: true,
+            attack_quorum: 0.75,
         }
     }
+}
 
-    pub fn with_cell_size(cell_size: f32) -> Self {
-        assert!(
-            cell_size > 1.0,
-            "Es inútil trabajar con celdas más pequeñas que un píxel"
-        );
-        let UVec2 {
-            x: columns,
-            y: rows,
-        } = (SCREEN_SIZE / cell_size).floor().as_uvec2();
-        Self::new(rows, columns, cell_size)
+#[derive(Resource, Debug, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct PackConfiguration {
+    packs: Vec<PackSettings>,
+}
+
+impl PackConfiguration {
+    pub fn empty() -> Self {
+        Self { packs: Vec::new() }
     }
 
-    pub fn cells(&self) -> &Cells {
-        &self.cells
+    pub fn add_pack(&mut self, settings: PackSettings) -> Pack {
+        self.packs.push(settings);
+        Pack(self.packs.len() - 1)
     }
 
-    pub fn cells_mut(&mut self) -> &mut Cells {
-        &mut self.cells
+    pub fn pack(&self, pack: Pack) -> Option<&PackSettings> {
+        self.packs.get(pack.0)
     }
 
-    pub fn columns(&self) -> u32 {
-        self.columns
+    pub fn pack_mut(&mut self, pack: Pack) -> Option<&mut PackSettings> {
+        self.packs.get_mut(pack.0)
     }
 
-    pub fn rows(&self) -> u32 {
-        self.rows
+    pub fn packs(&self) -> &[PackSettings] {
+        &self.packs
     }
 
-    pub fn cell_size(&self) -> f32 {
-        self.at_index(0).size()
+    pub fn packs_mut(&mut self) -> &mut [PackSettings] {
+        &mut self.packs
     }
 
-    pub fn grid_size(&self) -> Vec2 {
-        UVec2::new(self.columns, self.rows).as_vec2() * self.cell_size()
+    pub fn len(&self) -> usize {
+        self.packs.len()
     }
 
-    pub fn clear(&mut self) {
-        for cell in &mut self.cells {
-            cell.boids.clear();
-        }
+    pub fn is_empty(&self) -> bool {
+        self.packs.is_empty()
     }
+}
 
-    pub fn iter_radius(&self, centre: Vec2, radius: f32) -> SpatialGridInRadiusIter {
-        SpatialGridInRadiusIter::new(self, centre, radius)
+impl Default for PackConfiguration {
+    fn default() -> Self {
+        let mut packs = Self::empty();
+        packs.add_pack(PackSettings::new("Manada"));
+        packs
     }
+}
 
-    #[must_use = "No vas a usar este SpatialGridCell?"]
-    pub fn at(&self, row: usize, column: usize) -> &SpatialGridCell {
-        if column >= self.columns as usize {
-            panic!("{column} no puede ser mayor que {}", self.columns);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum TargetingStrategy {
+    #[default]
+    Nearest,
+    MostIsolated,
+    FlockCentroid,
+    Peripheral,
+    // The nearest prey, but crowds make the lock hard to get and easy to lose
+    Confusion,
+}
+
+impl TargetingStrategy {
+    pub const ALL: [Self; 5] = [
+        Self::Nearest,
+        Self::MostIsolated,
+        Self::FlockCentroid,
+        Self::Peripheral,
+        Self::Confusion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nearest => "Más cercano",
+            Self::MostIsolated => "Más aislado",
+            Self::FlockCentroid => "Centro de la bandada",
+            Self::Peripheral => "Periférico",
+            Self::Confusion => "Efecto de confusión",
         }
-        &self.cells[row * self.columns as usize + column]
     }
+}
 
-    #[must_use = "No vas a usar este SpatialGridCell?"]
-    pub fn at_mut(&mut self, row: usize, column: usize) -> &mut SpatialGridCell {
-        &mut self.cells[row * self.columns as usize + column]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum CaptureOutcome {
+    Despawn,
+    #[default]
+    Respawn,
+}
+
+impl CaptureOutcome {
+    pub const ALL: [Self; 2] = [Self::Despawn, Self::Respawn];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Despawn => "Eliminar",
+            Self::Respawn => "Reaparecer",
+        }
     }
+}
 
-    pub fn get(&self, row: usize, column: usize) -> Option<&SpatialGridCell> {
-        if column >= self.columns as usize {
-            None
+#[derive(Resource, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Resource)]
+pub struct CaptureStatistics {
+    pub attempts: u32,
+    pub captures: u32,
+}
+
+impl CaptureStatistics {
+    pub fn success_rate(&self) -> f32 {
+        if self.attempts == 0 {
+            0.0
         } else {
-            self.cells.get(row * self.columns as usize + column)
+            self.captures as f32 / self.attempts as f32
         }
     }
+}
 
-    #[must_use = "No vas a usar este SpatialGridCell?"]
-    pub fn at_world_position(&self, world_position: Vec2) -> &SpatialGridCell {
-        self.at_index(self.index_from_world_position(world_position))
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum OutOfGridPolicy {
+    #[default]
+    Clamp,
+    Overflow,
+    Correct,
+}
 
-    #[must_use = "No vas a usar este SpatialGridCell?"]
-    pub fn at_world_position_mut(&mut self, world_position: Vec2) -> &mut SpatialGridCell {
-        self.at_index_mut(self.index_from_world_position(world_position))
-    }
+impl OutOfGridPolicy {
+    pub const ALL: [Self; 3] = [Self::Clamp, Self::Overflow, Self::Correct];
 
-    pub fn try_at_world_position(&self, world_position: Vec2) -> Option<&SpatialGridCell> {
-        match self.try_index_from_world_position(world_position) {
-            Ok(index) => Some(self.at_index(index)),
-            Err(_) => None,
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Clamp => "Celda del borde",
+            Self::Overflow => "Celda de desbordamiento",
+            Self::Correct => "Devolver al mundo",
         }
     }
+}
 
-    #[must_use = "No vas a usar este SpatialGridCell?"]
-    pub fn at_index(&self, index: usize) -> &SpatialGridCell {
-        &self.cells[index]
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum Integrator {
+    ExplicitEuler,
+    #[default]
+    SemiImplicitEuler,
+    VelocityVerlet,
+    RungeKutta4,
+}
 
-    #[must_use = "No vas a usar este SpatialGridCell?"]
-    pub fn at_index_mut(&mut self, index: usize) -> &mut SpatialGridCell {
-        &mut self.cells[index]
-    }
+impl Integrator {
+    pub const ALL: [Self; 4] = [
+        Self::ExplicitEuler,
+        Self::SemiImplicitEuler,
+        Self::VelocityVerlet,
+        Self::RungeKutta4,
+    ];
 
-    pub fn index_from_world_position(&self, world_position: Vec2) -> usize {
-        self.try_index_from_world_position(world_position).unwrap()
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ExplicitEuler => "Euler explícito",
+            Self::SemiImplicitEuler => "Euler semiimplícito",
+            Self::VelocityVerlet => "Verlet de velocidades",
+            Self::RungeKutta4 => "Runge-Kutta 4 (viento y campos)",
+        }
     }
+}
 
-    pub fn try_index_from_world_position(&self, world_position: Vec2) -> Result<usize, String> {
-        let total_cells = self.rows * self.columns;
-        let half_size = self.grid_size() / 2.0;
-        if !(-half_size.x..half_size.x).contains(&world_position.x)
-            || !(-half_size.y..half_size.y).contains(&world_position.y)
-        {
-            return Err(format!(
-                "La posición {world_position} no entra en el rango [x: {}..{}, y: {}..{}]",
-                -half_size.x, half_size.x, -half_size.y, half_size.y
-            ));
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum SteeringModel {
+    #[default]
+    Direct,
+    Reynolds,
+}
+
+impl SteeringModel {
+    pub const ALL: [Self; 2] = [Self::Direct, Self::Reynolds];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Direct => "Directo (suma de velocidades)",
+            Self::Reynolds => "Reynolds (fuerza y aceleración)",
         }
-        let UVec2 { x: column, y: row } =
-            ((world_position + half_size) / self.cell_size()).as_uvec2();
-        let i = row * self.columns + column;
-        if i >= total_cells {
-            return Err(format!("La conversión posición global ({world_position}) -> índice debe dar menor que {total_cells}, pero ha dado {row} * {} + {column} = {i}", self.columns));
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum NeighbourhoodMode {
+    #[default]
+    Metric,
+    Topological,
+    Hybrid,
+}
+
+impl NeighbourhoodMode {
+    pub const ALL: [Self; 3] = [Self::Metric, Self::Topological, Self::Hybrid];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Metric => "Métrico (radio)",
+            Self::Topological => "Topológico (k vecinos)",
+            Self::Hybrid => "Híbrido (k vecinos dentro del radio)",
         }
-        Ok(i as usize)
+    }
+
+    pub fn is_metric(&self) -> bool {
+        *self != Self::Topological
     }
 }
 
-impl Debug for SpatialGrid {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for (i, cell) in self.cells.iter().enumerate() {
-            let i = i as u32;
-            writeln!(
-                f,
-                "Celda ({}, {}): {:?}",
-                i / self.rows,
-                i % self.columns,
-                cell
-            )?;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum CellSizePolicy {
+    #[default]
+    PerceptionRadius,
+    HalfPerceptionRadius,
+    Fixed,
+}
+
+impl CellSizePolicy {
+    pub const ALL: [Self; 3] = [
+        Self::PerceptionRadius,
+        Self::HalfPerceptionRadius,
+        Self::Fixed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PerceptionRadius => "Radio de percepción",
+            Self::HalfPerceptionRadius => "Mitad del radio de percepción",
+            Self::Fixed => "Fijo",
         }
-        Ok(())
     }
 }
 
-pub struct SpatialGridInRadiusIter<'g> {
-    spatial_grid: &'g SpatialGrid,
-    index: UVec2,
-    index_ranges: (RangeInclusive<u32>, RangeInclusive<u32>),
+#[derive(Resource, Reflect, Clone, Copy, Debug)]
+#[reflect(Resource)]
+pub struct WorldBounds {
+    pub rect: Rect,
+    pub follow_window: bool,
 }
 
-impl<'g> SpatialGridInRadiusIter<'g> {
-    pub fn new(spatial_grid: &'g SpatialGrid, centre: Vec2, radius: f32) -> Self {
-        let inf = centre - radius;
-        let sup = centre + radius;
-        let offset = spatial_grid.grid_size() / 2.0;
-        let reckon_indices_from = |point: Vec2| {
-            ((point + offset) / spatial_grid.cell_size())
-                .floor()
-                .as_uvec2()
-        };
-        let inf_index = reckon_indices_from(inf);
-        let sup_index = reckon_indices_from(sup);
-        let index_ranges = (inf_index.x..=sup_index.x, inf_index.y..=sup_index.y);
+impl WorldBounds {
+    pub fn new(rect: Rect) -> Self {
         Self {
-            spatial_grid,
-            index: inf_index,
-            index_ranges,
+            rect,
+            follow_window: false,
         }
     }
+
+    pub fn from_size(size: Vec2) -> Self {
+        Self::new(Rect::from_center_size(Vec2::ZERO, size))
+    }
+
+    pub fn following_window(mut self) -> Self {
+        self.follow_window = true;
+        self
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.rect.size()
+    }
+
+    pub fn centre(&self) -> Vec2 {
+        self.rect.center()
+    }
+
+    pub fn contains(&self, position: Vec2) -> bool {
+        self.rect.contains(position)
+    }
 }
 
-impl<'g> Iterator for SpatialGridInRadiusIter<'g> {
-    type Item = &'g SpatialGridCell;
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self::from_size(SCREEN_SIZE)
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let (x_range, y_range) = &self.index_ranges;
-        loop {
-            if !y_range.contains(&self.index.y) {
-                break None;
-            }
-            let cell = self
-                .spatial_grid
-                .get(self.index.y as usize, self.index.x as usize);
-            self.index.x += 1;
-            if !x_range.contains(&self.index.x) {
-                self.index.x = *x_range.start();
-                self.index.y += 1;
-            }
-            if cell.is_some() {
-                break cell;
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct SpeciesInteraction {
+    pub cohesion: f32,
+    pub alignment: f32,
+    pub separation: f32,
+    pub flee: f32,
+    pub hunt: f32,
+}
+
+impl SpeciesInteraction {
+    pub const RANGE: RangeInclusive<f32> = 0.0..=5.0;
+
+    pub const fn new(cohesion: f32, alignment: f32, separation: f32, flee: f32, hunt: f32) -> Self {
+        Self {
+            cohesion,
+            alignment,
+            separation,
+            flee,
+            hunt,
+        }
+    }
+
+    // Strangers keep their distance and nothing else
+    pub fn avoid(separation: f32) -> Self {
+        Self::new(0.0, 0.0, separation, 1.0, 1.0)
+    }
+}
+
+impl Default for SpeciesInteraction {
+    fn default() -> Self {
+        Self::new(1.0, 1.0, 1.0, 1.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Reflect)]
+pub struct SpeciesDefinition {
+    pub name: String,
+    pub colour: Color,
+    pub sprite: Option<Handle<Image>>,
+    pub predator: bool,
+}
+
+// Row `observer`, column `other`: how a boid of the first species reacts to one of the second
+#[derive(Resource, Debug, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct InteractionMatrix {
+    species: Vec<SpeciesDefinition>,
+    interactions: Vec<SpeciesInteraction>,
+}
+
+impl InteractionMatrix {
+    pub fn empty() -> Self {
+        Self {
+            species: Vec::new(),
+            interactions: Vec::new(),
+        }
+    }
+
+    pub fn add_species(&mut self, name: &str, colour: Color, predator: bool) -> Species {
+        let old_len = self.species.len();
+        let mut interactions = Vec::with_capacity((old_len + 1).pow(2));
+        for observer in 0..=old_len {
+            for other in 0..=old_len {
+                interactions.push(if observer < old_len && other < old_len {
+                    self.interactions[observer * old_len + other]
+                } else {
+                    SpeciesInteraction::default()
+                });
             }
         }
+        self.interactions = interactions;
+        self.species.push(SpeciesDefinition {
+            name: name.to_owned(),
+            colour,
+            sprite: None,
+            predator,
+        });
+        Species(old_len)
+    }
+
+    pub fn set_sprite(&mut self, species: Species, sprite: Handle<Image>) {
+        self.species[species.0].sprite = Some(sprite);
+    }
+
+    pub fn set_interaction(
+        &mut self,
+        observer: Species,
+        other: Species,
+        interaction: SpeciesInteraction,
+    ) {
+        let len = self.species.len();
+        self.interactions[observer.0 * len + other.0] = interaction;
+    }
+
+    pub fn set_mutual_interaction(
+        &mut self,
+        a: Species,
+        b: Species,
+        interaction: SpeciesInteraction,
+    ) {
+        self.set_interaction(a, b, interaction);
+        self.set_interaction(b, a, interaction);
+    }
+
+    pub fn interaction(&self, observer: usize, other: usize) -> &SpeciesInteraction {
+        let len = self.species.len();
+        if observer < len && other < len {
+            &self.interactions[observer * len + other]
+        } else {
+            const NEUTRAL: SpeciesInteraction = SpeciesInteraction::new(1.0, 1.0, 1.0, 1.0, 1.0);
+            &NEUTRAL
+        }
+    }
+
+    pub fn interaction_mut(
+        &mut self,
+        observer: Species,
+        other: Species,
+    ) -> &mut SpeciesInteraction {
+        let len = self.species.len();
+        &mut self.interactions[observer.0 * len + other.0]
+    }
+
+    pub fn definition(&self, species: Species) -> Option<&SpeciesDefinition> {
+        self.species.get(species.0)
+    }
+
+    pub fn species(&self) -> impl Iterator<Item = (Species, &SpeciesDefinition)> {
+        self.species
+            .iter()
+            .enumerate()
+            .map(|(i, definition)| (Species(i), definition))
+    }
+
+    pub fn prey_species(&self) -> impl Iterator<Item = Species> + '_ {
+        self.species()
+            .filter(|(_, definition)| !definition.predator)
+            .map(|(species, _)| species)
+    }
+
+    pub fn predator_species(&self) -> impl Iterator<Item = Species> + '_ {
+        self.species()
+            .filter(|(_, definition)| definition.predator)
+            .map(|(species, _)| species)
+    }
+
+    pub fn len(&self) -> usize {
+        self.species.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.species.is_empty()
+    }
+}
+
+impl Default for InteractionMatrix {
+    fn default() -> Self {
+        let mut matrix = Self::empty();
+        matrix.add_species("Bandada", Color::srgb(0.1, 1.0, 0.2), false);
+        matrix.add_species("Depredadores", Color::srgb(1.0, 0.2, 0.2), true);
+        matrix
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldOfView {
+    pub half_angle: f32,
+    pub blind_spot: f32,
+    pub peripheral_falloff: f32,
+}
+
+impl FieldOfView {
+    pub fn from_configuration(config: &BoidConfiguration) -> Self {
+        Self {
+            half_angle: config
+                .scalar_parametre(ParamId::VIEW_HALF_ANGLE)
+                .to_radians(),
+            blind_spot: config
+                .scalar_parametre(ParamId::BLIND_SPOT_ANGLE)
+                .to_radians(),
+            peripheral_falloff: config.scalar_parametre(ParamId::PERIPHERAL_FALLOFF),
+        }
+    }
+
+    // Half-angle actually seen once the rear blind spot is carved out of the cone
+    pub fn visible_half_angle(&self) -> f32 {
+        self.half_angle
+            .min(f32::consts::PI - self.blind_spot)
+            .max(0.0)
+    }
+
+    pub fn is_full_circle(&self) -> bool {
+        self.visible_half_angle() >= f32::consts::PI && self.peripheral_falloff <= 0.0
+    }
+
+    pub fn weight(&self, heading: Vec2, offset: Vec2, radius: f32) -> f32 {
+        if self.is_full_circle() || heading == Vec2::ZERO || offset == Vec2::ZERO {
+            return 1.0;
+        }
+        let half_angle = self.visible_half_angle();
+        let angle = heading.angle_to(offset).abs();
+        if angle > half_angle {
+            return 0.0;
+        }
+        let peripherality = if half_angle > 0.0 {
+            angle / half_angle
+        } else {
+            1.0
+        };
+        let remoteness = (offset.length() / radius.max(f32::EPSILON)).min(1.0);
+        1.0 - self.peripheral_falloff * peripherality * remoteness
+    }
+}
+
+impl Default for FieldOfView {
+    fn default() -> Self {
+        Self {
+            half_angle: f32::consts::PI,
+            blind_spot: 0.0,
+            peripheral_falloff: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct BoidRuleParametres<'a> {
+    pub entity: Entity,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub speed: f32,
+    pub predator: bool,
+    pub satiated: bool,
+    pub predator_state: PredatorState,
+    pub predator_target: Option<PredatorTarget>,
+    pub predator_waypoint: Option<Vec2>,
+    pub perception_radius: f32,
+    pub field_of_view: FieldOfView,
+    pub neighbourhood: &'a [Neighbour<'a>],
+    pub spatial_index: &'a dyn SpatialIndex,
+    pub force_fields: &'a [(Vec2, ForceField)],
+    pub obstacles: &'a [(Isometry2d, Obstacle)],
+    pub refuges: &'a [(Isometry2d, Refuge)],
+    pub species: usize,
+    pub interactions: &'a InteractionMatrix,
+    pub wind_currents: &'a [WindCurrent],
+    pub simulation_configuration: &'a SimulationConfiguration,
+    pub world_bounds: &'a WorldBounds,
+}
+
+impl<'a> BoidRuleParametres<'a> {
+    pub fn interaction_with(&self, other: &SpatialGridBoid) -> &'a SpeciesInteraction {
+        self.interactions.interaction(self.species, other.species)
+    }
+
+    // Everyone within `radius`, whatever the neighbourhood mode, for rules such as separation or
+    // flee that have to react to anything that close
+    pub fn neighbours(&self, radius: f32) -> impl Iterator<Item = Neighbour<'a>> + 'a {
+        self.in_sight(self.within(radius), radius)
+    }
+
+    // The neighbours cohesion and alignment follow. Topological neighbourhoods ignore distance
+    // altogether, so the k nearest count however far away they are
+    pub fn topological_neighbours(&self, radius: f32) -> impl Iterator<Item = Neighbour<'a>> + 'a {
+        let radius_squared = radius.squared();
+        let neighbours = match self.simulation_configuration.neighbourhood_mode {
+            NeighbourhoodMode::Topological => Either::Left(self.neighbourhood.iter().copied()),
+            NeighbourhoodMode::Hybrid if radius <= self.perception_radius => {
+                Either::Right(Either::Left(
+                    self.neighbourhood
+                        .iter()
+                        .copied()
+                        .filter(move |neighbour| neighbour.distance_squared < radius_squared),
+                ))
+            }
+            _ => Either::Right(Either::Right(self.within(radius))),
+        };
+        self.in_sight(neighbours, radius)
+    }
+
+    fn within(&self, radius: f32) -> impl Iterator<Item = Neighbour<'a>> + 'a {
+        let radius_squared = radius.squared();
+        // Only a metric neighbourhood is sure to hold every boid within the perception radius
+        let complete =
+            self.simulation_configuration.neighbourhood_mode == NeighbourhoodMode::Metric;
+        if complete && radius <= self.perception_radius {
+            Either::Left(
+                self.neighbourhood
+                    .iter()
+                    .copied()
+                    .filter(move |neighbour| neighbour.distance_squared < radius_squared),
+            )
+        } else {
+            let mut neighbours = Vec::new();
+            self.spatial_index.neighbours_within(
+                self.position,
+                radius,
+                Some(self.entity),
+                &mut neighbours,
+            );
+            Either::Right(neighbours.into_iter())
+        }
+    }
+
+    fn in_sight(
+        &self,
+        neighbours: impl Iterator<Item = Neighbour<'a>> + 'a,
+        radius: f32,
+    ) -> impl Iterator<Item = Neighbour<'a>> + 'a {
+        let (field_of_view, heading) = (self.field_of_view, self.velocity.normalize_or_zero());
+        neighbours.filter_map(move |neighbour| {
+            let weight = field_of_view.weight(heading, neighbour.offset, radius);
+            (weight > 0.0).then(|| neighbour.with_weight(weight))
+        })
+    }
+}
+
+pub trait Rule: Fn(BoidRuleParametres, &BoidConfiguration) -> Vec2 + Send + Sync + 'static {}
+impl<T: Fn(BoidRuleParametres, &BoidConfiguration) -> Vec2 + Send + Sync + 'static> Rule for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    Steering,
+    // Steering that only depends on where the boid is, so integrators may sample it mid-step
+    Field,
+    Drift,
+}
+
+pub struct BoidRule {
+    name: String,
+    kind: RuleKind,
+    priority: u32,
+    rule: Box<dyn Rule>,
+}
+
+impl BoidRule {
+    pub const DEFAULT_PRIORITY: u32 = 2;
+
+    pub fn new(name: &str, kind: RuleKind, rule: impl Rule) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+            priority: Self::DEFAULT_PRIORITY,
+            rule: Box::new(rule),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> RuleKind {
+        self.kind
+    }
+
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    pub fn apply(&self, parametres: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+        (self.rule)(parametres, config)
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct BoidRules(Vec<BoidRule>);
+
+impl BoidRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, rule: impl Rule) -> &mut Self {
+        self.push(BoidRule::new(name, RuleKind::Steering, rule))
+    }
+
+    pub fn add_field(&mut self, name: &str, rule: impl Rule) -> &mut Self {
+        self.push(BoidRule::new(name, RuleKind::Field, rule))
+    }
+
+    pub fn add_drift(&mut self, name: &str, rule: impl Rule) -> &mut Self {
+        self.push(BoidRule::new(name, RuleKind::Drift, rule))
+    }
+
+    pub fn replace(&mut self, name: &str, rule: impl Rule) -> bool {
+        match self.0.iter_mut().find(|boid_rule| boid_rule.name == name) {
+            Some(boid_rule) => {
+                boid_rule.rule = Box::new(rule);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Lower values are granted the force budget first under the Reynolds steering model
+    pub fn set_priority(&mut self, name: &str, priority: u32) -> bool {
+        match self.0.iter_mut().find(|boid_rule| boid_rule.name == name) {
+            Some(boid_rule) => {
+                boid_rule.priority = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|boid_rule| boid_rule.name != name);
+        self.0.len() != len
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|boid_rule| boid_rule.name == name)
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn steering(&self) -> impl Iterator<Item = &BoidRule> {
+        self.0
+            .iter()
+            .filter(|boid_rule| boid_rule.kind != RuleKind::Drift)
+    }
+
+    pub fn steering_by_priority(&self) -> impl Iterator<Item = &BoidRule> {
+        self.steering()
+            .sorted_by_key(|boid_rule| boid_rule.priority)
+    }
+
+    pub fn drift(&self) -> impl Iterator<Item = &BoidRule> {
+        self.0
+            .iter()
+            .filter(|boid_rule| boid_rule.kind == RuleKind::Drift)
+    }
+
+    fn push(&mut self, boid_rule: BoidRule) -> &mut Self {
+        assert!(
+            !self.contains(&boid_rule.name),
+            "Ya existe una regla llamada '{}'",
+            boid_rule.name
+        );
+        self.0.push(boid_rule);
+        self
+    }
+}
+
+impl<'a> IntoIterator for &'a BoidRules {
+    type Item = &'a BoidRule;
+    type IntoIter = std::slice::Iter<'a, BoidRule>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[derive(Resource)]
+pub struct BoidSpatialIndex {
+    kind: SpatialIndexKind,
+    settings: SpatialIndexSettings,
+    index: Box<dyn SpatialIndex>,
+}
+
+impl BoidSpatialIndex {
+    pub fn new(kind: SpatialIndexKind) -> Self {
+        let settings = SpatialIndexSettings::default();
+        Self {
+            kind,
+            settings,
+            index: kind.create(&settings),
+        }
+    }
+
+    pub fn kind(&self) -> SpatialIndexKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: SpatialIndexKind) {
+        if kind != self.kind {
+            self.kind = kind;
+            self.index = kind.create(&self.settings);
+        }
+    }
+
+    pub fn settings(&self) -> &SpatialIndexSettings {
+        &self.settings
+    }
+
+    pub fn configure(&mut self, settings: SpatialIndexSettings) {
+        if settings != self.settings {
+            self.settings = settings;
+            self.index.configure(&settings);
+        }
+    }
+}
+
+impl Default for BoidSpatialIndex {
+    fn default() -> Self {
+        Self::new(SpatialIndexKind::default())
+    }
+}
+
+impl Deref for BoidSpatialIndex {
+    type Target = dyn SpatialIndex;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.index
+    }
+}
+
+impl DerefMut for BoidSpatialIndex {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.index
+    }
+}
+
+#[derive(Resource, Reflect, InspectorOptions)]
+#[reflect(Resource, InspectorOptions)]
+pub struct SpatialGrid {
+    boids: Vec<SpatialGridBoid>,
+    cell_starts: Vec<u32>,
+    rows: u32,
+    columns: u32,
+    cell_size: f32,
+    origin: Vec2,
+    wrap_size: Option<Vec2>,
+    out_of_grid_policy: OutOfGridPolicy,
+    #[reflect(ignore)]
+    scratch: SpatialGridScratch,
+}
+
+// Buffers reused by every rebuild so that sorting the flock does not allocate each tick
+#[derive(Default)]
+struct SpatialGridScratch {
+    cell_indices: Vec<u32>,
+    histograms: Vec<u32>,
+}
+
+// Lets every chunk write into its own slots of the sorted array at the same time
+#[derive(Clone, Copy)]
+struct ScatterTarget(*mut SpatialGridBoid);
+
+// SAFETY: the chunks never write to the same slot, see `SpatialGrid::rebuild_in`
+unsafe impl Send for ScatterTarget {}
+unsafe impl Sync for ScatterTarget {}
+
+impl ScatterTarget {
+    // SAFETY: `slot` must be in bounds and written by a single chunk
+    unsafe fn write(self, slot: u32, boid: SpatialGridBoid) {
+        unsafe { *self.0.add(slot as usize) = boid };
+    }
+}
+
+impl SpatialGrid {
+    const MIN_CHUNK: usize = 1024;
+
+    pub fn new(rows: u32, columns: u32, cell_size: f32) -> Self {
+        Self::from_origin(
+            -UVec2::new(columns, rows).as_vec2() * cell_size / 2.0,
+            rows,
+            columns,
+            cell_size,
+        )
+    }
+
+    pub fn from_origin(origin: Vec2, rows: u32, columns: u32, cell_size: f32) -> Self {
+        assert!(
+            rows > 0 && columns > 0,
+            "Prohibido crear un SpatialGrid unidimensional o nildimensional o de dimensiones negativas"
+        );
+        assert!(
+            cell_size > 1.0,
+            "Es inútil trabajar con celdas más pequeñas que un píxel"
+        );
+        Self {
+            boids: Vec::new(),
+            cell_starts: vec![0; (rows * columns) as usize + 2],
+            rows,
+            columns,
+            cell_size,
+            origin,
+            wrap_size: None,
+            out_of_grid_policy: OutOfGridPolicy::default(),
+            scratch: SpatialGridScratch::default(),
+        }
+    }
+
+    pub fn with_cell_size(cell_size: f32) -> Self {
+        Self::with_bounds(WorldBounds::default().rect, cell_size)
+    }
+
+    pub fn with_bounds(bounds: Rect, cell_size: f32) -> Self {
+        assert!(
+            cell_size > 1.0,
+            "Es inútil trabajar con celdas más pequeñas que un píxel"
+        );
+        let UVec2 {
+            x: columns,
+            y: rows,
+        } = Self::dimensions_for(bounds, cell_size);
+        Self::from_origin(bounds.min, rows, columns, cell_size)
+    }
+
+    pub fn fits(&self, bounds: Rect, cell_size: f32) -> bool {
+        self.origin == bounds.min
+            && self.cell_size == cell_size
+            && UVec2::new(self.columns, self.rows) == Self::dimensions_for(bounds, cell_size)
+    }
+
+    fn dimensions_for(bounds: Rect, cell_size: f32) -> UVec2 {
+        (bounds.size() / cell_size)
+            .ceil()
+            .as_uvec2()
+            .max(UVec2::ONE)
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = SpatialGridCell<'_>> {
+        (0..self.cell_count()).map(|index| self.at_index(index))
+    }
+
+    pub fn boids(&self) -> &[SpatialGridBoid] {
+        &self.boids
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    pub fn cell_count(&self) -> usize {
+        (self.rows * self.columns) as usize
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn grid_size(&self) -> Vec2 {
+        UVec2::new(self.columns, self.rows).as_vec2() * self.cell_size()
+    }
+
+    pub fn origin(&self) -> Vec2 {
+        self.origin
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::from_corners(self.origin, self.origin + self.grid_size())
+    }
+
+    pub fn wrap_size(&self) -> Option<Vec2> {
+        self.wrap_size
+    }
+
+    pub fn set_wrap_size(&mut self, wrap_size: Option<Vec2>) {
+        self.wrap_size = wrap_size;
+    }
+
+    pub fn out_of_grid_policy(&self) -> OutOfGridPolicy {
+        self.out_of_grid_policy
+    }
+
+    pub fn set_out_of_grid_policy(&mut self, policy: OutOfGridPolicy) {
+        self.out_of_grid_policy = policy;
+    }
+
+    pub fn offset(&self, from: Vec2, to: Vec2) -> Vec2 {
+        let offset = to - from;
+        match self.wrap_size {
+            Some(size) => offset - size * (offset / size).round(),
+            None => offset,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.boids.clear();
+        self.cell_starts.fill(0);
+    }
+
+    pub fn overflow_index(&self) -> usize {
+        self.cell_count()
+    }
+
+    pub fn overflow(&self) -> SpatialGridCell<'_> {
+        SpatialGridCell {
+            grid_pos: UVec2::new(self.columns, self.rows),
+            rect: self.rect(),
+            boids: self.boids_in(self.overflow_index()),
+        }
+    }
+
+    pub fn cell_index_for(&self, world_position: Vec2) -> usize {
+        match self.try_index_from_world_position(world_position) {
+            Ok(index) => index,
+            Err(_) if self.out_of_grid_policy == OutOfGridPolicy::Overflow => self.overflow_index(),
+            Err(_) => self.clamped_index_from_world_position(world_position),
+        }
+    }
+
+    // Counting sort in three passes. Each chunk of boids computes its cells and its own
+    // histogram in parallel, a serial prefix sum turns the histograms into `cell_starts` and into
+    // the first slot of every cell for each chunk, and each chunk then scatters its boids in
+    // parallel from those slots
+    pub fn rebuild_in(&mut self, boids: &[SpatialGridBoid], task_pool: &TaskPool) {
+        let buckets = self.cell_count() + 1;
+        let chunk_size = boids
+            .len()
+            .div_ceil(task_pool.thread_num().max(1))
+            .max(Self::MIN_CHUNK);
+        let chunks = boids.len().div_ceil(chunk_size).max(1);
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.cell_indices.resize(boids.len(), 0);
+        scratch.histograms.clear();
+        scratch.histograms.resize(chunks * buckets, 0);
+
+        let grid = &*self;
+        task_pool.scope(|scope| {
+            for ((chunk, indices), histogram) in boids
+                .chunks(chunk_size)
+                .zip(scratch.cell_indices.chunks_mut(chunk_size))
+                .zip(scratch.histograms.chunks_mut(buckets))
+            {
+                scope.spawn(async move {
+                    for (boid, index) in chunk.iter().zip(indices) {
+                        *index = grid.cell_index_for(boid.position).min(buckets - 1) as u32;
+                        histogram[*index as usize] += 1;
+                    }
+                });
+            }
+        });
+
+        // Histograms become cursors: where each chunk starts writing into each cell
+        self.cell_starts.clear();
+        self.cell_starts.resize(buckets + 1, 0);
+        let mut start = 0;
+        for bucket in 0..buckets {
+            self.cell_starts[bucket] = start;
+            for chunk in 0..chunks {
+                let slot = &mut scratch.histograms[chunk * buckets + bucket];
+                let count = *slot;
+                *slot = start;
+                start += count;
+            }
+        }
+        self.cell_starts[buckets] = start;
+
+        self.boids.clear();
+        self.boids.extend_from_slice(boids);
+        let target = ScatterTarget(self.boids.as_mut_ptr());
+        task_pool.scope(|scope| {
+            for ((chunk, indices), cursors) in boids
+                .chunks(chunk_size)
+                .zip(scratch.cell_indices.chunks(chunk_size))
+                .zip(scratch.histograms.chunks_mut(buckets))
+            {
+                scope.spawn(async move {
+                    for (&boid, &index) in chunk.iter().zip(indices) {
+                        let cursor = &mut cursors[index as usize];
+                        // SAFETY: this chunk owns the slots from its cursor for each cell up to
+                        // its own count, which lie inside the cell's range in `boids`
+                        unsafe { target.write(*cursor, boid) };
+                        *cursor += 1;
+                    }
+                });
+            }
+        });
+        self.scratch = scratch;
+    }
+
+    pub fn iter_radius(&self, centre: Vec2, radius: f32) -> SpatialGridInRadiusIter<'_> {
+        SpatialGridInRadiusIter::new(self, centre, radius)
+    }
+
+    pub fn neighbours_within(
+        &self,
+        position: Vec2,
+        radius: f32,
+        exclude: Option<Entity>,
+    ) -> impl Iterator<Item = Neighbour<'_>> {
+        let radius_squared = radius.squared();
+        self.iter_radius(position, radius)
+            .flat_map(|cell| cell.cell_boids())
+            .filter(move |boid| Some(boid.entity) != exclude)
+            .map(move |boid| Neighbour::new(boid, self.offset(position, boid.position)))
+            .filter(move |neighbour| neighbour.distance_squared < radius_squared)
+    }
+
+    pub fn k_nearest(&self, position: Vec2, k: usize) -> Vec<Neighbour<'_>> {
+        let mut nearest = Vec::with_capacity(k);
+        self.k_nearest_into(position, k, None, &mut nearest);
+        nearest
+    }
+
+    // Visits cells in square rings around the query cell until the k-th candidate is closer than
+    // anything the next ring could hold
+    pub fn k_nearest_into<'g>(
+        &'g self,
+        position: Vec2,
+        k: usize,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'g>>,
+    ) {
+        out.clear();
+        if k == 0 || self.boids.is_empty() {
+            return;
+        }
+        let consider = |cell: SpatialGridCell<'g>, out: &mut Vec<Neighbour<'g>>| {
+            for boid in cell.cell_boids() {
+                if Some(boid.entity) != exclude {
+                    out.push(Neighbour::new(boid, self.offset(position, boid.position)));
+                }
+            }
+        };
+        consider(self.overflow(), out);
+        let dimensions = IVec2::new(self.columns as i32, self.rows as i32);
+        let wrapped = match self.wrap_size {
+            Some(size) => self.origin + (position - self.origin).rem_euclid(size),
+            None => position,
+        };
+        let centre = ((wrapped - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, dimensions - 1);
+        // Offset ranges that reach every cell exactly once
+        let (low, high) = match self.wrap_size {
+            Some(_) => (-(dimensions - 1) / 2, dimensions / 2),
+            None => (-centre, dimensions - 1 - centre),
+        };
+        let cell_at = |offset: IVec2| {
+            let IVec2 { x: column, y: row } = (centre + offset).rem_euclid(dimensions);
+            self.at(row as usize, column as usize)
+        };
+        // The last row and column are cut short by the seam when the world isn't a whole number of
+        // cells, so crossing it can cost less than a full cell
+        let seam_slack = self
+            .wrap_size
+            .map_or(0.0, |size| (self.grid_size() - size).max_element().max(0.0));
+        let last_ring = (-low).max(high).max_element();
+        for ring in 0..=last_ring {
+            for dy in (-ring).max(low.y)..=ring.min(high.y) {
+                if dy.abs() == ring {
+                    for dx in (-ring).max(low.x)..=ring.min(high.x) {
+                        consider(cell_at(IVec2::new(dx, dy)), out);
+                    }
+                } else {
+                    for dx in [-ring, ring] {
+                        if (low.x..=high.x).contains(&dx) && (dx != 0 || ring != 0) {
+                            consider(cell_at(IVec2::new(dx, dy)), out);
+                        }
+                    }
+                }
+            }
+            if out.len() >= k {
+                sort_and_truncate(out, k);
+                if out[k - 1].distance_squared
+                    <= (ring as f32 * self.cell_size - seam_slack)
+                        .max(0.0)
+                        .squared()
+                {
+                    return;
+                }
+            }
+        }
+        sort_and_truncate(out, k);
+    }
+
+    pub fn query_rect(&self, rect: Rect) -> impl Iterator<Item = &SpatialGridBoid> {
+        self.cells_in_rect(rect)
+            .chain([self.overflow()])
+            .flat_map(|cell| cell.cell_boids())
+            .filter(move |boid| contains_half_open(rect, boid.position))
+    }
+
+    pub fn cells_in_rect(&self, rect: Rect) -> impl Iterator<Item = SpatialGridCell<'_>> {
+        let grid_rect = self.rect();
+        let cells = if rect.max.cmplt(grid_rect.min).any() || rect.min.cmpge(grid_rect.max).any() {
+            UVec2::ONE..=UVec2::ZERO
+        } else {
+            let last = UVec2::new(self.columns - 1, self.rows - 1);
+            let reckon_indices_from = |point: Vec2| {
+                ((point - self.origin) / self.cell_size)
+                    .floor()
+                    .max(Vec2::ZERO)
+                    .as_uvec2()
+                    .min(last)
+            };
+            reckon_indices_from(rect.min)..=reckon_indices_from(rect.max)
+        };
+        let (start, end) = (*cells.start(), *cells.end());
+        (start.y..=end.y)
+            .flat_map(move |row| (start.x..=end.x).map(move |column| (row, column)))
+            .map(|(row, column)| self.at(row as usize, column as usize))
+    }
+
+    #[must_use = "No vas a usar este SpatialGridCell?"]
+    pub fn at(&self, row: usize, column: usize) -> SpatialGridCell<'_> {
+        if column >= self.columns as usize {
+            panic!("{column} no puede ser mayor que {}", self.columns);
+        }
+        self.at_index(row * self.columns as usize + column)
+    }
+
+    pub fn get(&self, row: usize, column: usize) -> Option<SpatialGridCell<'_>> {
+        if column >= self.columns as usize || row >= self.rows as usize {
+            None
+        } else {
+            Some(self.at_index(row * self.columns as usize + column))
+        }
+    }
+
+    #[must_use = "No vas a usar este SpatialGridCell?"]
+    pub fn at_world_position(&self, world_position: Vec2) -> SpatialGridCell<'_> {
+        self.at_index(self.index_from_world_position(world_position))
+    }
+
+    pub fn try_at_world_position(&self, world_position: Vec2) -> Option<SpatialGridCell<'_>> {
+        match self.try_index_from_world_position(world_position) {
+            Ok(index) => Some(self.at_index(index)),
+            Err(_) => None,
+        }
+    }
+
+    #[must_use = "No vas a usar este SpatialGridCell?"]
+    pub fn at_index(&self, index: usize) -> SpatialGridCell<'_> {
+        assert!(
+            index < self.cell_count(),
+            "El índice {index} no puede ser mayor que {}",
+            self.cell_count()
+        );
+        let row = index as u32 / self.columns;
+        let column = index as u32 % self.columns;
+        SpatialGridCell::new(
+            row,
+            column,
+            self.cell_size,
+            self.origin + (Vec2::new(column as f32, row as f32) + 0.5) * self.cell_size,
+            self.boids_in(index),
+        )
+    }
+
+    pub fn index_from_world_position(&self, world_position: Vec2) -> usize {
+        self.try_index_from_world_position(world_position).unwrap()
+    }
+
+    pub fn clamped_index_from_world_position(&self, world_position: Vec2) -> usize {
+        let UVec2 { x: column, y: row } = ((world_position - self.origin) / self.cell_size())
+            .floor()
+            .max(Vec2::ZERO)
+            .as_uvec2()
+            .min(UVec2::new(self.columns - 1, self.rows - 1));
+        (row * self.columns + column) as usize
+    }
+
+    pub fn try_index_from_world_position(&self, world_position: Vec2) -> Result<usize, String> {
+        let total_cells = self.rows * self.columns;
+        let Rect { min, max } = self.rect();
+        if !(min.x..max.x).contains(&world_position.x)
+            || !(min.y..max.y).contains(&world_position.y)
+        {
+            return Err(format!(
+                "La posición {world_position} no entra en el rango [x: {}..{}, y: {}..{}]",
+                min.x, max.x, min.y, max.y
+            ));
+        }
+        let UVec2 { x: column, y: row } =
+            ((world_position - self.origin) / self.cell_size()).as_uvec2();
+        let i = row * self.columns + column;
+        if i >= total_cells {
+            return Err(format!("La conversión posición global ({world_position}) -> índice debe dar menor que {total_cells}, pero ha dado {row} * {} + {column} = {i}", self.columns));
+        }
+        Ok(i as usize)
+    }
+
+    fn boids_in(&self, index: usize) -> &[SpatialGridBoid] {
+        match self.cell_starts.get(index..=index + 1) {
+            Some(&[start, end]) => &self.boids[start as usize..end as usize],
+            _ => &[],
+        }
+    }
+}
+
+impl Debug for SpatialGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for cell in self.cells() {
+            writeln!(
+                f,
+                "Celda ({}, {}): {:?}",
+                cell.grid_pos.y, cell.grid_pos.x, cell
+            )?;
+        }
+        Ok(())
+    }
+}
+
+type AxisCells = std::iter::Chain<
+    RangeInclusive<u32>,
+    std::iter::Flatten<std::option::IntoIter<RangeInclusive<u32>>>,
+>;
+
+fn axis_cells(inf: f32, sup: f32, cell_size: f32, cells: u32, wrap_size: Option<f32>) -> AxisCells {
+    let last = cells - 1;
+    let cell_range = |inf: f32, sup: f32| {
+        if sup < 0.0 || inf >= (cells as f32) * cell_size {
+            return RangeInclusive::new(1, 0);
+        }
+        let start = (inf / cell_size).floor().max(0.0) as u32;
+        let end = ((sup / cell_size).floor() as u32).min(last);
+        start..=end
+    };
+    let (first, second) = match wrap_size {
+        Some(size) if sup - inf >= size => (0..=last, None),
+        Some(size) => {
+            let shift = (inf / size).floor() * size;
+            let (inf, sup) = (inf - shift, sup - shift);
+            let first = cell_range(inf, sup.min(size - f32::EPSILON));
+            if sup < size {
+                (first, None)
+            } else {
+                let second = cell_range(0.0, sup - size);
+                if second.end() >= first.start() {
+                    (0..=*first.end().max(second.end()), None)
+                } else {
+                    (first, Some(second))
+                }
+            }
+        }
+        None => (cell_range(inf, sup), None),
+    };
+    first.chain(second.into_iter().flatten())
+}
+
+pub struct SpatialGridInRadiusIter<'g> {
+    spatial_grid: &'g SpatialGrid,
+    rows: AxisCells,
+    columns: AxisCells,
+    row_columns: AxisCells,
+    row: Option<u32>,
+    overflow: bool,
+}
+
+impl<'g> SpatialGridInRadiusIter<'g> {
+    pub fn new(spatial_grid: &'g SpatialGrid, centre: Vec2, radius: f32) -> Self {
+        let inf = centre - radius - spatial_grid.origin();
+        let sup = centre + radius - spatial_grid.origin();
+        let cell_size = spatial_grid.cell_size();
+        let wrap_size = spatial_grid.wrap_size();
+        let columns = axis_cells(
+            inf.x,
+            sup.x,
+            cell_size,
+            spatial_grid.columns(),
+            wrap_size.map(|size| size.x),
+        );
+        let mut rows = axis_cells(
+            inf.y,
+            sup.y,
+            cell_size,
+            spatial_grid.rows(),
+            wrap_size.map(|size| size.y),
+        );
+        Self {
+            spatial_grid,
+            row: rows.next(),
+            rows,
+            row_columns: columns.clone(),
+            columns,
+            overflow: !spatial_grid.overflow().cell_boids().is_empty(),
+        }
+    }
+}
+
+impl<'g> Iterator for SpatialGridInRadiusIter<'g> {
+    type Item = SpatialGridCell<'g>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(row) = self.row else {
+                if self.overflow {
+                    self.overflow = false;
+                    break Some(self.spatial_grid.overflow());
+                }
+                break None;
+            };
+            match self.row_columns.next() {
+                Some(column) => break Some(self.spatial_grid.at(row as usize, column as usize)),
+                None => {
+                    self.row = self.rows.next();
+                    self.row_columns = self.columns.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::TaskPoolBuilder;
+
+    #[test]
+    fn parallel_rebuild_matches_sequential_counting_sort() {
+        let bounds = Rect::from_center_size(Vec2::ZERO, Vec2::new(1000.0, 600.0));
+        let mut grid = SpatialGrid::with_bounds(bounds, 50.0);
+        grid.set_out_of_grid_policy(OutOfGridPolicy::Overflow);
+        // Scattered over and slightly beyond the grid so the overflow bucket gets boids too
+        let boids = (0..5 * SpatialGrid::MIN_CHUNK as u32)
+            .map(|i| {
+                let position = Vec2::new(
+                    (i * 7919 % 1100) as f32 - 550.0,
+                    (i * 104_729 % 700) as f32 - 350.0,
+                );
+                SpatialGridBoid::new(Entity::from_raw(i), position, Vec2::ZERO, false)
+            })
+            .collect::<Vec<_>>();
+
+        let buckets = grid.cell_count() + 1;
+        let indices = boids
+            .iter()
+            .map(|boid| grid.cell_index_for(boid.position))
+            .collect::<Vec<_>>();
+        let mut cell_starts = vec![0; buckets + 1];
+        for &index in &indices {
+            cell_starts[index + 1] += 1;
+        }
+        for bucket in 0..buckets {
+            cell_starts[bucket + 1] += cell_starts[bucket];
+        }
+        let mut cursors = cell_starts.clone();
+        let mut sorted = vec![None; boids.len()];
+        for (boid, &index) in boids.iter().zip(&indices) {
+            sorted[cursors[index] as usize] = Some(boid.entity);
+            cursors[index] += 1;
+        }
+
+        let task_pool = TaskPoolBuilder::new().num_threads(4).build();
+        // Twice, so that reused scratch buffers are exercised as well
+        for _ in 0..2 {
+            grid.rebuild_in(&boids, &task_pool);
+            assert_eq!(grid.cell_starts, cell_starts);
+            assert_eq!(
+                grid.boids()
+                    .iter()
+                    .map(|boid| Some(boid.entity))
+                    .collect::<Vec<_>>(),
+                sorted
+            );
+        }
+        assert!(!grid.overflow().cell_boids().is_empty());
     }
 }
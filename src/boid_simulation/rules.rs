@@ -0,0 +1,237 @@
+use super::{components::*, resources::*};
+use crate::helpers::*;
+use bevy::{math::FloatPow, prelude::*};
+
+pub fn cohesion(params: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+    if params.predator {
+        return Vec2::ZERO;
+    }
+    let avoidance_radius_squared = config
+        .scalar_parametre(ParamId::SEPARATION_RADIUS)
+        .squared();
+    let mut perceived_centre = OVec2::default();
+    let mut total_weight = 0.0;
+    for other_boid in params
+        .topological_neighbours(config.scalar_parametre(ParamId::VIEW_RADIUS))
+        .filter(|other_boid| !other_boid.predator)
+    {
+        let weight = other_boid.weight * params.interaction_with(other_boid.boid).cohesion;
+        if other_boid.distance_squared >= avoidance_radius_squared && weight > 0.0 {
+            perceived_centre += (params.position + other_boid.offset) * weight;
+            total_weight += weight;
+        }
+    }
+    if total_weight > 0.0 {
+        perceived_centre /= total_weight;
+    }
+    (perceived_centre.get().unwrap_or(params.position) - params.position)
+        * config.scalar_parametre(ParamId::COHESION_WEIGHT)
+}
+
+pub fn separation(params: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+    let avoidance_radius_squared = config
+        .scalar_parametre(ParamId::SEPARATION_RADIUS)
+        .squared();
+    let weight = config.scalar_parametre(ParamId::SEPARATION_WEIGHT);
+    let mut push_force = Vec2::ZERO;
+    for other_boid in params
+        .neighbours(config.scalar_parametre(ParamId::SEPARATION_RADIUS))
+        .filter(|other_boid| other_boid.predator == params.predator)
+    {
+        let r = other_boid.offset;
+        let distance_squared = other_boid.distance_squared;
+        push_force -= (other_boid.weight
+            * params.interaction_with(other_boid.boid).separation
+            * weight
+            * avoidance_radius_squared
+            * r.normalize_or(params.velocity)
+            / if distance_squared < 0.1 {
+                1.0
+            } else {
+                distance_squared
+            })
+        .clamp_length_max(avoidance_radius_squared);
+    }
+    push_force
+}
+
+pub fn alignment(params: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+    if params.predator {
+        return Vec2::ZERO;
+    }
+    let avoidance_radius_squared = config
+        .scalar_parametre(ParamId::SEPARATION_RADIUS)
+        .squared();
+    let mut perceived_velocity = OVec2::default();
+    let mut total_weight = 0.0;
+    for other_boid in params
+        .topological_neighbours(config.scalar_parametre(ParamId::VIEW_RADIUS))
+        .filter(|other_boid| !other_boid.predator)
+    {
+        let weight = other_boid.weight * params.interaction_with(other_boid.boid).alignment;
+        if other_boid.distance_squared >= avoidance_radius_squared && weight > 0.0 {
+            perceived_velocity += other_boid.velocity * weight;
+            total_weight += weight;
+        }
+    }
+    if total_weight > 0.0 {
+        perceived_velocity /= total_weight;
+    }
+    (perceived_velocity.get().unwrap_or(params.velocity) - params.velocity)
+        * config.scalar_parametre(ParamId::ALIGNMENT_WEIGHT)
+}
+
+pub fn flee(params: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+    if params.predator {
+        return Vec2::ZERO;
+    }
+    // Average boldness leaves the flee weight as is; the boldest ignore predators altogether
+    let weight = config.scalar_parametre(ParamId::FLEE_WEIGHT)
+        * 2.0
+        * (1.0 - config.scalar_parametre(ParamId::BOLDNESS));
+    // Prey inside a refuge feel safe
+    if params
+        .refuges
+        .iter()
+        .any(|(isometry, refuge)| refuge.contains(*isometry, params.position))
+    {
+        return Vec2::ZERO;
+    }
+    let mut push_force = Vec2::ZERO;
+    let mut alarm = 0.0;
+    for other_boid in params
+        .neighbours(config.scalar_parametre(ParamId::VIEW_RADIUS))
+        .filter(|other_boid| other_boid.predator)
+    {
+        let flee = other_boid.weight * params.interaction_with(other_boid.boid).flee;
+        push_force -= flee * weight * other_boid.offset.normalize_or_zero() * params.speed;
+        alarm += flee;
+    }
+    // With a predator in sight, head for the closest refuge within reach as well
+    let refuge_range = params.simulation_configuration.refuge_range;
+    let closest_refuge = params
+        .refuges
+        .iter()
+        .map(|(isometry, refuge)| refuge.signed_distance(*isometry, params.position))
+        .filter(|(distance, _)| *distance <= refuge_range)
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+    if let Some((_, normal)) = closest_refuge.filter(|_| alarm > 0.0) {
+        push_force -= alarm.min(1.0) * weight * normal * params.speed;
+    }
+    push_force
+}
+
+pub fn hunt(params: BoidRuleParametres, _config: &BoidConfiguration) -> Vec2 {
+    if !params.predator || params.satiated {
+        return Vec2::ZERO;
+    }
+    let behaviour = &params.simulation_configuration.predator_behaviour;
+    let intensity = match params.predator_state {
+        PredatorState::Patrol => {
+            return params.simulation_configuration.predator_hunt_weight * params.velocity
+        }
+        PredatorState::Stalk => behaviour.stalk_intensity,
+        PredatorState::Attack => 1.0,
+        PredatorState::Rest => return Vec2::ZERO,
+    };
+    intensity
+        * params.simulation_configuration.predator_hunt_weight
+        * match params.predator_target {
+            // Encircling pack members head for their slot around the prey rather than the prey
+            Some(target) if params.predator_state == PredatorState::Stalk => {
+                let goal = params.predator_waypoint.unwrap_or(target.position);
+                params
+                    .interactions
+                    .interaction(params.species, target.species)
+                    .hunt
+                    * params
+                        .spatial_index
+                        .offset(params.position, goal)
+                        .normalize_or(params.velocity)
+                    * params.speed
+            }
+            Some(target) => {
+                params
+                    .interactions
+                    .interaction(params.species, target.species)
+                    .hunt
+                    * params
+                        .spatial_index
+                        .offset(params.position, target.position)
+                        .normalize_or(params.velocity)
+                    * params.speed
+            }
+            None => params.velocity,
+        }
+}
+
+pub fn force_fields(params: BoidRuleParametres, _config: &BoidConfiguration) -> Vec2 {
+    let mut push_force = Vec2::ZERO;
+    for (point, ff) in params.force_fields {
+        let distance = point.distance(params.position);
+        let charge = ff.charge.abs();
+        if distance <= charge {
+            push_force += ff.charge.signum()
+                * (params.position - *point).normalize_or(params.velocity.normalize_or_zero())
+                * params.speed
+                * (1.0 - (1.0 - (distance / charge - 1.0).squared()).sqrt());
+        }
+    }
+    push_force
+}
+
+pub fn avoid_obstacles(params: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+    // Probes along the heading, paired with how urgently a hit there has to be dodged
+    const PROBES: [(f32, f32); 3] = [(0.25, 1.0), (0.5, 0.75), (1.0, 0.5)];
+    let heading = params.velocity.normalize_or_zero();
+    if heading == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    let weight = config.scalar_parametre(ParamId::OBSTACLE_AVOIDANCE_WEIGHT);
+    let look_ahead = config.scalar_parametre(ParamId::OBSTACLE_LOOK_AHEAD);
+    let radius = Obstacle::BOID_RADIUS * config.scale;
+    let mut steering = Vec2::ZERO;
+    for (isometry, obstacle) in params.obstacles {
+        for (fraction, urgency) in PROBES {
+            let probe = params.position + heading * look_ahead * fraction;
+            let (distance, normal) = obstacle.signed_distance(*isometry, probe);
+            if distance < radius {
+                // Slide along the surface on whichever side the boid is already turning to
+                let tangent = normal.perp() * normal.perp().dot(heading).signum();
+                let desired = (tangent + normal).normalize_or_zero() * params.speed;
+                steering += (desired - params.velocity) * weight * urgency;
+                break;
+            }
+        }
+    }
+    steering
+}
+
+pub fn steer_back(params: BoidRuleParametres, _config: &BoidConfiguration) -> Vec2 {
+    let simulation_configuration = params.simulation_configuration;
+    if simulation_configuration.boundary_mode != BoundaryMode::SteerBack {
+        return Vec2::ZERO;
+    }
+    let margin = simulation_configuration.boundary_margin.max(f32::EPSILON);
+    let inner = params.world_bounds.rect.inflate(-margin);
+    let outside = (inner.min - params.position).max(Vec2::ZERO)
+        - (params.position - inner.max).max(Vec2::ZERO);
+    (outside / margin).clamp(Vec2::NEG_ONE, Vec2::ONE)
+        * simulation_configuration.boundary_turn_force
+}
+
+pub fn strong_wind(_params: BoidRuleParametres, config: &BoidConfiguration) -> Vec2 {
+    Vec2::from_angle(config.scalar_parametre(ParamId::WIND_ANGLE).to_radians())
+        * config.scalar_parametre(ParamId::WIND_SPEED)
+}
+
+pub fn wind_currents(params: BoidRuleParametres, _config: &BoidConfiguration) -> Vec2 {
+    let mut offset_velocity = Vec2::ZERO;
+    for wind_current in params.wind_currents {
+        if let Some((t, _, _)) = wind_current.closest(params.position) {
+            offset_velocity +=
+                wind_current.curve().velocity(t).normalize_or_zero() * wind_current.wind_speed;
+        }
+    }
+    offset_velocity
+}
@@ -0,0 +1,631 @@
+use super::resources::*;
+use crate::helpers::*;
+use bevy::{
+    math::FloatPow,
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+use std::{any::Any, collections::HashMap, f32, ops::Range};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpatialIndexSettings {
+    pub bounds: Rect,
+    pub cell_size: f32,
+    pub wrap: bool,
+    pub out_of_grid_policy: OutOfGridPolicy,
+}
+
+impl SpatialIndexSettings {
+    pub fn wrap_bounds(&self) -> Option<Rect> {
+        self.wrap.then_some(self.bounds)
+    }
+
+    pub fn correct_position(&self, position: Vec2) -> Vec2 {
+        let Rect { min, max } = self.bounds;
+        let max = max - Vec2::splat(0.1);
+        if self.wrap {
+            (min + (position - min).rem_euclid(self.bounds.size())).clamp(min, max)
+        } else {
+            position.clamp(min, max)
+        }
+    }
+}
+
+impl Default for SpatialIndexSettings {
+    fn default() -> Self {
+        Self {
+            bounds: WorldBounds::default().rect,
+            cell_size: 200.0,
+            wrap: true,
+            out_of_grid_policy: OutOfGridPolicy::default(),
+        }
+    }
+}
+
+pub trait SpatialIndex: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    fn configure(&mut self, settings: &SpatialIndexSettings);
+
+    fn rebuild(&mut self, boids: &[SpatialGridBoid]);
+
+    fn boids(&self) -> &[SpatialGridBoid];
+
+    fn extent(&self) -> Rect;
+
+    fn wrap_bounds(&self) -> Option<Rect>;
+
+    fn for_each_in_rect<'a>(&'a self, rect: Rect, visit: &mut dyn FnMut(&'a SpatialGridBoid));
+
+    fn contains(&self, _position: Vec2) -> bool {
+        true
+    }
+
+    fn for_each_candidate<'a>(
+        &'a self,
+        centre: Vec2,
+        radius: f32,
+        visit: &mut dyn FnMut(&'a SpatialGridBoid),
+    ) {
+        for rect in wrapped_rects(centre, radius, self.wrap_bounds()) {
+            self.for_each_in_rect(rect, visit);
+        }
+    }
+
+    fn offset(&self, from: Vec2, to: Vec2) -> Vec2 {
+        let offset = to - from;
+        match self.wrap_bounds() {
+            Some(bounds) => offset - bounds.size() * (offset / bounds.size()).round(),
+            None => offset,
+        }
+    }
+
+    fn neighbours_within<'a>(
+        &'a self,
+        centre: Vec2,
+        radius: f32,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'a>>,
+    ) {
+        let radius_squared = radius.squared();
+        self.for_each_candidate(centre, radius, &mut |boid| {
+            if Some(boid.entity) == exclude {
+                return;
+            }
+            let neighbour = Neighbour::new(boid, self.offset(centre, boid.position));
+            if neighbour.distance_squared < radius_squared {
+                out.push(neighbour);
+            }
+        });
+    }
+
+    fn k_nearest<'a>(
+        &'a self,
+        centre: Vec2,
+        k: usize,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'a>>,
+    ) {
+        k_nearest_by_expansion(self, centre, k, exclude, out);
+    }
+
+    fn debug_rects(&self, _visit: &mut dyn FnMut(Rect)) {}
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+pub fn k_nearest_by_expansion<'a, T: SpatialIndex + ?Sized>(
+    index: &'a T,
+    centre: Vec2,
+    k: usize,
+    exclude: Option<Entity>,
+    out: &mut Vec<Neighbour<'a>>,
+) {
+    out.clear();
+    if k == 0 || index.boids().is_empty() {
+        return;
+    }
+    let extent = index.extent();
+    let farthest = [
+        extent.min,
+        extent.max,
+        Vec2::new(extent.min.x, extent.max.y),
+        Vec2::new(extent.max.x, extent.min.y),
+    ]
+    .into_iter()
+    .map(|corner| corner.distance(centre))
+    .fold(0.0, f32::max);
+    let density = index.boids().len() as f32 / extent.size().element_product().max(1.0);
+    let mut radius = (k as f32 / (density * f32::consts::PI)).sqrt().max(1.0);
+    loop {
+        out.clear();
+        index.neighbours_within(centre, radius, exclude, out);
+        if out.len() >= k || radius > farthest {
+            break;
+        }
+        radius *= 2.0;
+    }
+    sort_and_truncate(out, k);
+}
+
+pub(crate) fn sort_and_truncate(neighbours: &mut Vec<Neighbour>, k: usize) {
+    if neighbours.len() > k {
+        neighbours
+            .select_nth_unstable_by(k, |a, b| a.distance_squared.total_cmp(&b.distance_squared));
+        neighbours.truncate(k);
+    }
+    neighbours.sort_unstable_by(|a, b| a.distance_squared.total_cmp(&b.distance_squared));
+}
+
+fn wrapped_intervals(
+    centre: f32,
+    radius: f32,
+    range: Option<(f32, f32)>,
+) -> [Option<Range<f32>>; 2] {
+    let Some((min, max)) = range else {
+        return [Some(centre - radius..centre + radius), None];
+    };
+    let size = max - min;
+    let radius = radius.min(size / 2.0);
+    let shift = ((centre - radius - min) / size).floor() * size;
+    let (start, end) = (centre - radius - shift, centre + radius - shift);
+    if end <= max {
+        [Some(start..end), None]
+    } else {
+        [Some(start..max), Some(min..end - size)]
+    }
+}
+
+pub fn wrapped_rects(centre: Vec2, radius: f32, wrap_bounds: Option<Rect>) -> Vec<Rect> {
+    let xs = wrapped_intervals(
+        centre.x,
+        radius,
+        wrap_bounds.map(|bounds| (bounds.min.x, bounds.max.x)),
+    );
+    let ys = wrapped_intervals(
+        centre.y,
+        radius,
+        wrap_bounds.map(|bounds| (bounds.min.y, bounds.max.y)),
+    );
+    let mut rects = Vec::with_capacity(4);
+    for x in xs.iter().flatten() {
+        for y in ys.iter().flatten() {
+            rects.push(Rect::new(x.start, y.start, x.end, y.end));
+        }
+    }
+    rects
+}
+
+pub(crate) fn contains_half_open(rect: Rect, position: Vec2) -> bool {
+    (rect.min.x..rect.max.x).contains(&position.x) && (rect.min.y..rect.max.y).contains(&position.y)
+}
+
+fn extent_of(boids: &[SpatialGridBoid]) -> Rect {
+    boids
+        .iter()
+        .map(|boid| Rect::from_center_size(boid.position, Vec2::ZERO))
+        .reduce(|a, b| a.union(b))
+        .unwrap_or_default()
+}
+
+fn task_pool() -> &'static TaskPool {
+    ComputeTaskPool::get_or_init(TaskPool::default)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum SpatialIndexKind {
+    #[default]
+    UniformGrid,
+    HashedGrid,
+    KdTree,
+}
+
+impl SpatialIndexKind {
+    pub const ALL: [Self; 3] = [Self::UniformGrid, Self::HashedGrid, Self::KdTree];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UniformGrid => "Rejilla uniforme",
+            Self::HashedGrid => "Rejilla dispersa",
+            Self::KdTree => "Árbol k-d",
+        }
+    }
+
+    pub fn create(&self, settings: &SpatialIndexSettings) -> Box<dyn SpatialIndex> {
+        let mut index: Box<dyn SpatialIndex> = match self {
+            Self::UniformGrid => Box::new(SpatialGrid::with_bounds(
+                settings.bounds,
+                settings.cell_size,
+            )),
+            Self::HashedGrid => Box::new(HashedGrid::new(settings.cell_size)),
+            Self::KdTree => Box::new(KdTree::new()),
+        };
+        index.configure(settings);
+        index
+    }
+}
+
+impl SpatialIndex for SpatialGrid {
+    fn name(&self) -> &'static str {
+        SpatialIndexKind::UniformGrid.name()
+    }
+
+    fn configure(&mut self, settings: &SpatialIndexSettings) {
+        if !self.fits(settings.bounds, settings.cell_size) {
+            *self = SpatialGrid::with_bounds(settings.bounds, settings.cell_size);
+        }
+        self.set_wrap_size(settings.wrap.then(|| settings.bounds.size()));
+        self.set_out_of_grid_policy(settings.out_of_grid_policy);
+    }
+
+    fn rebuild(&mut self, boids: &[SpatialGridBoid]) {
+        self.rebuild_in(boids, task_pool());
+    }
+
+    fn boids(&self) -> &[SpatialGridBoid] {
+        SpatialGrid::boids(self)
+    }
+
+    fn extent(&self) -> Rect {
+        self.rect()
+    }
+
+    fn wrap_bounds(&self) -> Option<Rect> {
+        self.wrap_size()
+            .map(|size| Rect::from_corners(self.origin(), self.origin() + size))
+    }
+
+    fn contains(&self, position: Vec2) -> bool {
+        self.try_index_from_world_position(position).is_ok()
+    }
+
+    fn for_each_in_rect<'a>(&'a self, rect: Rect, visit: &mut dyn FnMut(&'a SpatialGridBoid)) {
+        self.query_rect(rect).for_each(visit);
+    }
+
+    fn for_each_candidate<'a>(
+        &'a self,
+        centre: Vec2,
+        radius: f32,
+        visit: &mut dyn FnMut(&'a SpatialGridBoid),
+    ) {
+        for cell in self.iter_radius(centre, radius) {
+            for boid in cell.cell_boids() {
+                visit(boid);
+            }
+        }
+    }
+
+    fn offset(&self, from: Vec2, to: Vec2) -> Vec2 {
+        SpatialGrid::offset(self, from, to)
+    }
+
+    fn neighbours_within<'a>(
+        &'a self,
+        centre: Vec2,
+        radius: f32,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'a>>,
+    ) {
+        out.extend(SpatialGrid::neighbours_within(
+            self, centre, radius, exclude,
+        ));
+    }
+
+    fn k_nearest<'a>(
+        &'a self,
+        centre: Vec2,
+        k: usize,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'a>>,
+    ) {
+        self.k_nearest_into(centre, k, exclude, out);
+    }
+
+    fn debug_rects(&self, visit: &mut dyn FnMut(Rect)) {
+        for cell in self.cells() {
+            visit(Rect::from_center_size(
+                cell.location(),
+                Vec2::splat(cell.size()),
+            ));
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct HashedGrid {
+    cell_size: f32,
+    wrap_bounds: Option<Rect>,
+    boids: Vec<SpatialGridBoid>,
+    cells: HashMap<IVec2, Range<usize>>,
+    extent: Rect,
+}
+
+impl HashedGrid {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(
+            cell_size > 1.0,
+            "Es inútil trabajar con celdas más pequeñas que un píxel"
+        );
+        Self {
+            cell_size,
+            wrap_bounds: None,
+            boids: Vec::new(),
+            cells: HashMap::new(),
+            extent: Rect::default(),
+        }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn cell_key(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn occupied_cells(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+impl SpatialIndex for HashedGrid {
+    fn name(&self) -> &'static str {
+        SpatialIndexKind::HashedGrid.name()
+    }
+
+    fn configure(&mut self, settings: &SpatialIndexSettings) {
+        self.cell_size = settings.cell_size;
+        self.wrap_bounds = settings.wrap_bounds();
+    }
+
+    fn rebuild(&mut self, boids: &[SpatialGridBoid]) {
+        let cell_size = self.cell_size;
+        let key = |boid: &SpatialGridBoid| {
+            let key = (boid.position / cell_size).floor().as_ivec2();
+            (key.y, key.x)
+        };
+        self.boids.clear();
+        self.boids.extend_from_slice(boids);
+        self.boids.sort_unstable_by_key(key);
+        self.cells.clear();
+        let mut start = 0;
+        for (i, boid) in self.boids.iter().enumerate().skip(1) {
+            if key(boid) != key(&self.boids[start]) {
+                self.cells
+                    .insert(self.cell_key(self.boids[start].position), start..i);
+                start = i;
+            }
+        }
+        if !self.boids.is_empty() {
+            self.cells.insert(
+                self.cell_key(self.boids[start].position),
+                start..self.boids.len(),
+            );
+        }
+        self.extent = extent_of(&self.boids);
+    }
+
+    fn boids(&self) -> &[SpatialGridBoid] {
+        &self.boids
+    }
+
+    fn extent(&self) -> Rect {
+        self.extent
+    }
+
+    fn wrap_bounds(&self) -> Option<Rect> {
+        self.wrap_bounds
+    }
+
+    fn for_each_in_rect<'a>(&'a self, rect: Rect, visit: &mut dyn FnMut(&'a SpatialGridBoid)) {
+        let min = self.cell_key(rect.min);
+        let max = self.cell_key(rect.max);
+        let cells_in_rect = (max - min + 1).max(IVec2::ZERO).as_uvec2();
+        let mut visit_cell = |range: &Range<usize>| {
+            for boid in &self.boids[range.clone()] {
+                if contains_half_open(rect, boid.position) {
+                    visit(boid);
+                }
+            }
+        };
+        if (cells_in_rect.x as usize).saturating_mul(cells_in_rect.y as usize) > self.cells.len() {
+            for (key, range) in &self.cells {
+                if key.cmpge(min).all() && key.cmple(max).all() {
+                    visit_cell(range);
+                }
+            }
+        } else {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    if let Some(range) = self.cells.get(&IVec2::new(x, y)) {
+                        visit_cell(range);
+                    }
+                }
+            }
+        }
+    }
+
+    fn debug_rects(&self, visit: &mut dyn FnMut(Rect)) {
+        for key in self.cells.keys() {
+            let min = key.as_vec2() * self.cell_size;
+            visit(Rect::from_corners(min, min + self.cell_size));
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct KdTree {
+    wrap_bounds: Option<Rect>,
+    boids: Vec<SpatialGridBoid>,
+    extent: Rect,
+}
+
+impl KdTree {
+    const LEAF_SIZE: usize = 8;
+
+    pub fn new() -> Self {
+        Self {
+            wrap_bounds: None,
+            boids: Vec::new(),
+            extent: Rect::default(),
+        }
+    }
+
+    fn build(boids: &mut [SpatialGridBoid], depth: usize) {
+        if boids.len() <= Self::LEAF_SIZE {
+            return;
+        }
+        let axis = depth % 2;
+        let mid = boids.len() / 2;
+        boids.select_nth_unstable_by(mid, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+        let (left, right) = boids.split_at_mut(mid);
+        Self::build(left, depth + 1);
+        Self::build(&mut right[1..], depth + 1);
+    }
+
+    fn query_rect<'a>(
+        boids: &'a [SpatialGridBoid],
+        depth: usize,
+        rect: Rect,
+        visit: &mut dyn FnMut(&'a SpatialGridBoid),
+    ) {
+        if boids.len() <= Self::LEAF_SIZE {
+            for boid in boids {
+                if contains_half_open(rect, boid.position) {
+                    visit(boid);
+                }
+            }
+            return;
+        }
+        let axis = depth % 2;
+        let mid = boids.len() / 2;
+        let split = &boids[mid];
+        if contains_half_open(rect, split.position) {
+            visit(split);
+        }
+        if rect.min[axis] <= split.position[axis] {
+            Self::query_rect(&boids[..mid], depth + 1, rect, visit);
+        }
+        if rect.max[axis] >= split.position[axis] {
+            Self::query_rect(&boids[mid + 1..], depth + 1, rect, visit);
+        }
+    }
+
+    fn query_nearest<'a>(
+        boids: &'a [SpatialGridBoid],
+        depth: usize,
+        centre: Vec2,
+        k: usize,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'a>>,
+    ) {
+        let consider = |boid: &'a SpatialGridBoid, out: &mut Vec<Neighbour<'a>>| {
+            if Some(boid.entity) == exclude {
+                return;
+            }
+            let neighbour = Neighbour::new(boid, boid.position - centre);
+            if out.len() < k {
+                out.push(neighbour);
+            } else if let Some((worst, _)) = out
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.distance_squared.total_cmp(&b.distance_squared))
+            {
+                if neighbour.distance_squared < out[worst].distance_squared {
+                    out[worst] = neighbour;
+                }
+            }
+        };
+        if boids.len() <= Self::LEAF_SIZE {
+            for boid in boids {
+                consider(boid, out);
+            }
+            return;
+        }
+        let axis = depth % 2;
+        let mid = boids.len() / 2;
+        let split = &boids[mid];
+        consider(split, out);
+        let difference = centre[axis] - split.position[axis];
+        let (near, far) = if difference <= 0.0 {
+            (&boids[..mid], &boids[mid + 1..])
+        } else {
+            (&boids[mid + 1..], &boids[..mid])
+        };
+        Self::query_nearest(near, depth + 1, centre, k, exclude, out);
+        let worst = out
+            .iter()
+            .map(|neighbour| neighbour.distance_squared)
+            .fold(0.0, f32::max);
+        if out.len() < k || difference.squared() < worst {
+            Self::query_nearest(far, depth + 1, centre, k, exclude, out);
+        }
+    }
+}
+
+impl Default for KdTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpatialIndex for KdTree {
+    fn name(&self) -> &'static str {
+        SpatialIndexKind::KdTree.name()
+    }
+
+    fn configure(&mut self, settings: &SpatialIndexSettings) {
+        self.wrap_bounds = settings.wrap_bounds();
+    }
+
+    fn rebuild(&mut self, boids: &[SpatialGridBoid]) {
+        self.boids.clear();
+        self.boids.extend_from_slice(boids);
+        Self::build(&mut self.boids, 0);
+        self.extent = extent_of(&self.boids);
+    }
+
+    fn boids(&self) -> &[SpatialGridBoid] {
+        &self.boids
+    }
+
+    fn extent(&self) -> Rect {
+        self.extent
+    }
+
+    fn wrap_bounds(&self) -> Option<Rect> {
+        self.wrap_bounds
+    }
+
+    fn for_each_in_rect<'a>(&'a self, rect: Rect, visit: &mut dyn FnMut(&'a SpatialGridBoid)) {
+        Self::query_rect(&self.boids, 0, rect, visit);
+    }
+
+    fn k_nearest<'a>(
+        &'a self,
+        centre: Vec2,
+        k: usize,
+        exclude: Option<Entity>,
+        out: &mut Vec<Neighbour<'a>>,
+    ) {
+        if self.wrap_bounds.is_some() {
+            return k_nearest_by_expansion(self, centre, k, exclude, out);
+        }
+        out.clear();
+        if k > 0 {
+            Self::query_nearest(&self.boids, 0, centre, k, exclude, out);
+        }
+        sort_and_truncate(out, k);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,88 +1,136 @@
-use super::{bundles::*, components::*, resources::*};
+use super::{
+    behaviours::*, bundles::*, components::*, events::*, resources::*, rules, spatial_index::*,
+};
 use crate::{asset_related::resources::*, helpers::*, states::*};
-use bevy::{color::palettes::css::*, math::FloatPow, prelude::*};
+use bevy::{color::palettes::css::*, prelude::*, utils::Parallel, window::PrimaryWindow};
 use core::f32;
 use itertools::Itertools;
 use rand::Rng;
 
-pub fn setup_boid_parametres(mut config: ResMut<BoidConfiguration>) {
-    config
-        .add_scalar_parametre("Radio de separación", 50.0, 1.0..=100.0)
-        .add_scalar_parametre("Radio de visión", 100.0, 1.0..=200.0)
-        .add_scalar_parametre("Peso de cohesión", 0.25, 0.0..=1.0)
-        .add_scalar_parametre("Peso de separación", 1.0, 0.0..=5.0)
-        .add_scalar_parametre("Peso de alineamiento", 0.125, 0.0..=1.0)
-        .add_scalar_parametre("Ángulo del viento", -120.0, -180.0..=180.0)
-        .add_scalar_parametre(
-            "Velocidad del viento",
-            100.0,
-            0.0..=(BoidConfiguration::highest_speed() * 2.0),
-        )
-        .add_scalar_parametre("Peso de huida", 0.5, 0.0..=1.0);
-}
-
 pub fn clear_simulation(
     mut commands: Commands,
-    simulation_entities: Query<Entity, Or<(With<Boid>, With<WindCurrent>, With<ForceField>)>>,
+    simulation_entities: Query<
+        Entity,
+        Or<(
+            With<Boid>,
+            With<WindCurrent>,
+            With<ForceField>,
+            With<Obstacle>,
+            With<Refuge>,
+        )>,
+    >,
+    mut capture_statistics: ResMut<CaptureStatistics>,
 ) {
     for entity in simulation_entities {
         commands.entity(entity).despawn();
     }
+    *capture_statistics = CaptureStatistics::default();
 }
 
 pub fn setup_simulation(
     mut commands: Commands,
     boid_configuration: Res<BoidConfiguration>,
     simulation_configuration: Res<SimulationConfiguration>,
-    spatial_grid: Res<SpatialGrid>,
-    image_assets: Res<ImageAssets>,
+    world_bounds: Res<WorldBounds>,
+    interactions: Res<InteractionMatrix>,
+    packs: Res<PackConfiguration>,
+    image_assets: Option<Res<ImageAssets>>,
     mut app_next_state: ResMut<NextState<SimulationState>>,
 ) {
     let mut rng = rand::rng();
     let pi = f32::consts::PI;
-    let bounds = spatial_grid.grid_size() / 2.0;
-    for _ in 0..simulation_configuration.normal_boids {
+    let Rect { min, max } = world_bounds.rect;
+    let default_sprite = image_assets.map(|image_assets| image_assets.boid_sprite.clone());
+    let looks = |species: Species, fallback: Color| {
+        let definition = interactions.definition(species);
+        (
+            definition
+                .and_then(|definition| definition.sprite.clone())
+                .or_else(|| default_sprite.clone()),
+            definition.map_or(fallback, |definition| definition.colour),
+        )
+    };
+    // Boids are dealt round-robin among the species of their role
+    let prey_species = interactions.prey_species().collect_vec();
+    let predator_species = interactions.predator_species().collect_vec();
+    for i in 0..simulation_configuration.normal_boids as usize {
         let angle = rng.random_range(-pi..=pi);
-        commands.spawn(
+        let (boid_params, speed) = boid_configuration
+            .personality
+            .sample(&boid_configuration, &mut rng);
+        let species = prey_species
+            .get(i % prey_species.len().max(1))
+            .copied()
+            .unwrap_or_default();
+        let (sprite, colour) = looks(species, Color::srgb(0.1, 1.0, 0.2));
+        let mut boid = spawn_boid(
+            &mut commands,
             BoidBundle::start()
                 .name("Boid")
-                .boid(boid_configuration.average_speed(), angle)
-                .sprite(image_assets.boid_sprite.clone(), Color::srgb(0.1, 1.0, 0.2))
+                .species(species)
+                .boid(speed, angle)
                 .transform(
                     angle,
                     Vec2::new(
-                        rng.random_range(-bounds.x..=bounds.x),
-                        rng.random_range(-bounds.y..=bounds.y),
+                        rng.random_range(min.x..=max.x),
+                        rng.random_range(min.y..=max.y),
                     ),
                 )
                 .build(),
+            sprite,
+            colour,
         );
+        if !boid_params.is_empty() {
+            boid.insert(boid_params);
+        }
     }
-    commands.spawn((
+    let testing_species = prey_species.first().copied().unwrap_or_default();
+    spawn_boid(
+        &mut commands,
         BoidBundle::start()
             .name("Boid de pruebas")
-            .sprite(image_assets.boid_sprite.clone(), Color::srgb(0.1, 0.1, 1.0))
+            .species(testing_species)
+            .transform(0.0, world_bounds.centre())
             .build(),
-        BoidTestingUnit::default(),
-    ));
+        looks(testing_species, Color::WHITE).0,
+        Color::srgb(0.1, 0.1, 1.0),
+    )
+    .insert(BoidTestingUnit::default());
 
     // Predators
-    for _ in 0..simulation_configuration.predators {
+    for i in 0..simulation_configuration.predators as usize {
         let angle = rng.random_range(-pi..=pi);
-        commands.spawn((
+        let species = predator_species
+            .get(i % predator_species.len().max(1))
+            .copied()
+            .unwrap_or_default();
+        let (sprite, colour) = looks(species, Color::srgb(1.0, 0.2, 0.2));
+        let (boid_params, _) = boid_configuration
+            .personality
+            .sample(&boid_configuration, &mut rng);
+        let mut predator = spawn_boid(
+            &mut commands,
             BoidBundle::start()
                 .name("Boid depredador")
-                .sprite(image_assets.boid_sprite.clone(), Color::srgb(1.0, 0.2, 0.2))
+                .species(species)
                 .transform(
                     angle,
                     Vec2::new(
-                        rng.random_range(-bounds.x..=bounds.x),
-                        rng.random_range(-bounds.y..=bounds.y),
+                        rng.random_range(min.x..=max.x),
+                        rng.random_range(min.y..=max.y),
                     ),
                 )
                 .build(),
-            BoidPredator,
-        ));
+            sprite,
+            colour,
+        );
+        predator.insert(BoidPredator);
+        if !packs.is_empty() {
+            predator.insert(Pack(i % packs.len()));
+        }
+        if !boid_params.is_empty() {
+            predator.insert(boid_params);
+        }
     }
 
     // Wind currents
@@ -102,268 +150,1115 @@ pub fn setup_simulation(
     commands.spawn(ForceFieldBundle::new(80.0, Vec2::new(-400.0, 220.0)));
     commands.spawn(ForceFieldBundle::new(-120.0, Vec2::new(550.0, -200.0)));
 
+    // Obstacles
+    commands.spawn(ObstacleBundle::new(
+        ObstacleShape::Circle { radius: 60.0 },
+        Vec2::new(0.0, -250.0),
+    ));
+    commands.spawn(ObstacleBundle::new(
+        ObstacleShape::Box {
+            half_size: Vec2::new(80.0, 40.0),
+        },
+        Vec2::new(-300.0, -150.0),
+    ));
+    commands.spawn(ObstacleBundle::new(
+        ObstacleShape::Polygon {
+            vertices: vec![
+                Vec2::new(-60.0, -50.0),
+                Vec2::new(70.0, -40.0),
+                Vec2::new(0.0, 60.0),
+            ],
+        },
+        Vec2::new(300.0, 250.0),
+    ));
+    commands.spawn(ObstacleBundle::new(
+        ObstacleShape::Wall {
+            start: Vec2::new(-150.0, 0.0),
+            end: Vec2::new(150.0, 0.0),
+        },
+        Vec2::new(0.0, 300.0),
+    ));
+
+    // Refuges
+    commands.spawn(RefugeBundle::new(
+        RefugeShape::Circle { radius: 90.0 },
+        Vec2::new(-550.0, -300.0),
+    ));
+    commands.spawn(
+        RefugeBundle::new(
+            RefugeShape::Polygon {
+                vertices: vec![
+                    Vec2::new(-80.0, -60.0),
+                    Vec2::new(80.0, -60.0),
+                    Vec2::new(100.0, 40.0),
+                    Vec2::new(0.0, 90.0),
+                    Vec2::new(-100.0, 40.0),
+                ],
+            },
+            Vec2::new(550.0, 300.0),
+        )
+        .with_policy(RefugePolicy::NoCapture),
+    );
+
     // Switch to next state
     app_next_state.set(SimulationState::Running);
 }
 
-pub fn update_spatial_grid(
-    boids: Query<(Entity, &Transform, &Boid)>,
-    mut spatial_grid: ResMut<SpatialGrid>,
+pub fn setup_boid_rules(mut rules: ResMut<BoidRules>) {
+    rules
+        .add("Cohesión", rules::cohesion)
+        .add("Separación", rules::separation)
+        .add("Alineamiento", rules::alignment)
+        .add("Huida", rules::flee)
+        .add("Caza", rules::hunt)
+        .add_field("Campos de fuerza", rules::force_fields)
+        .add("Bordes", rules::steer_back)
+        .add("Obstáculos", rules::avoid_obstacles)
+        .add_drift("Viento fuerte", rules::strong_wind)
+        .add_drift("Corrientes de viento", rules::wind_currents);
+    for avoidance in ["Separación", "Huida", "Bordes", "Obstáculos"] {
+        rules.set_priority(avoidance, 0);
+    }
+    rules.set_priority("Campos de fuerza", 1);
+}
+
+fn spawn_boid<'a>(
+    commands: &'a mut Commands,
+    boid: BoidBundle,
+    sprite: Option<Handle<Image>>,
+    colour: Color,
+) -> EntityCommands<'a> {
+    let mut entity = commands.spawn(boid);
+    if let Some(image) = sprite {
+        entity.insert(Sprite {
+            image,
+            color: colour,
+            ..default()
+        });
+    }
+    entity
+}
+
+pub fn follow_window_size(
+    mut world_bounds: ResMut<WorldBounds>,
+    window: Option<Single<&Window, With<PrimaryWindow>>>,
 ) {
-    spatial_grid.clear();
-    for (entity, transform, boid) in boids {
-        let position = transform.translation.xy();
-        spatial_grid
-            .at_world_position_mut(position)
-            .push(SpatialGridBoid::new(entity, position, boid.velocity()));
+    let Some(window) = window else {
+        return;
+    };
+    let size = window.size();
+    if world_bounds.follow_window && world_bounds.size() != size && size.min_element() > 0.0 {
+        world_bounds.rect = Rect::from_center_size(world_bounds.centre(), size);
+    }
+}
+
+pub fn update_spatial_index(
+    mut boids: Query<(
+        Entity,
+        &mut Transform,
+        &Boid,
+        Has<BoidPredator>,
+        Option<&Species>,
+    )>,
+    overrides: Query<Ref<BoidParams>>,
+    mut removed_overrides: RemovedComponents<BoidParams>,
+    predators: Query<(), With<BoidPredator>>,
+    world_bounds: Res<WorldBounds>,
+    boid_configuration: Res<BoidConfiguration>,
+    simulation_configuration: Res<SimulationConfiguration>,
+    mut spatial_index: ResMut<BoidSpatialIndex>,
+    mut out_of_grid: EventWriter<BoidOutOfGrid>,
+    mut grid_boids: Local<Parallel<Vec<SpatialGridBoid>>>,
+    mut out_of_grid_events: Local<Parallel<Vec<BoidOutOfGrid>>>,
+    mut collected: Local<Vec<SpatialGridBoid>>,
+    mut largest_override: Local<f32>,
+) {
+    // Per-boid radii only change with their overrides, so their maximum is kept between ticks
+    if boid_configuration.is_changed()
+        || removed_overrides.read().count() > 0
+        || overrides.iter().any(|params| params.is_changed())
+    {
+        *largest_override = overrides
+            .iter()
+            .map(|params| boid_configuration.perception_radius_with(&params))
+            .fold(0.0, f32::max);
+    }
+    let mut largest_radius = boid_configuration
+        .perception_radius()
+        .max(*largest_override);
+    if !predators.is_empty() {
+        largest_radius =
+            largest_radius.max(simulation_configuration.predator_behaviour.detection_radius);
+    }
+    let settings = SpatialIndexSettings {
+        bounds: world_bounds.rect,
+        cell_size: simulation_configuration.cell_size(largest_radius),
+        wrap: simulation_configuration.boundary_mode == BoundaryMode::Wrap,
+        out_of_grid_policy: simulation_configuration.out_of_grid_policy,
+    };
+    spatial_index.configure(settings);
+    let index = &**spatial_index;
+    boids
+        .par_iter_mut()
+        .for_each(|(entity, mut transform, boid, predator, species)| {
+            let mut position = transform.translation.xy();
+            if !index.contains(position) {
+                warn_once!(
+                    "La posición {position} no entra en {} ({:?})",
+                    index.name(),
+                    index.extent()
+                );
+                out_of_grid_events
+                    .borrow_local_mut()
+                    .push(BoidOutOfGrid { entity, position });
+                if settings.out_of_grid_policy == OutOfGridPolicy::Correct {
+                    position = settings.correct_position(position);
+                    transform.translation = position.extend(transform.translation.z);
+                }
+            }
+            grid_boids.borrow_local_mut().push(
+                SpatialGridBoid::new(entity, position, boid.velocity(), predator)
+                    .with_species(species.map_or(0, |species| species.0)),
+            );
+        });
+    collected.clear();
+    collected.extend(grid_boids.drain());
+    spatial_index.rebuild(&collected);
+    out_of_grid.write_batch(out_of_grid_events.drain());
+}
+
+// Only the entities some behaviour refers to are sampled
+pub fn update_steering_targets(
+    targeted: Query<AnyOf<(&Seek, &Flee, &Arrive, &Pursue, &Evade, &FollowLeader)>>,
+    targets: Query<(&Transform, Option<&Boid>)>,
+    mut steering_targets: ResMut<SteeringTargets>,
+) {
+    steering_targets.clear();
+    for (seek, flee, arrive, pursue, evade, follow_leader) in &targeted {
+        let entities = [
+            seek.map(|seek| seek.target),
+            flee.map(|flee| flee.target),
+            arrive.map(|arrive| arrive.target),
+        ]
+        .into_iter()
+        .filter_map(|target| match target {
+            Some(SteeringTarget::Entity(entity)) => Some(entity),
+            _ => None,
+        })
+        .chain(pursue.map(|pursue| pursue.target))
+        .chain(evade.map(|evade| evade.target))
+        .chain(follow_leader.map(|follow_leader| follow_leader.leader));
+        for entity in entities {
+            if steering_targets.get(entity).is_some() {
+                continue;
+            }
+            if let Ok((transform, boid)) = targets.get(entity) {
+                steering_targets.insert(
+                    entity,
+                    transform.translation.xy(),
+                    boid.map_or(Vec2::ZERO, Boid::velocity),
+                );
+            }
+        }
+    }
+}
+
+// Paths edited in place, from code or the inspector, or cloned through reflection without their
+// samples, are sampled again before anyone follows them
+pub fn resample_paths(mut paths: Query<&mut FollowPath, Changed<FollowPath>>) {
+    for mut follow_path in &mut paths {
+        follow_path.bypass_change_detection().resample();
     }
 }
 
 pub fn update_boids(
-    mut boids: Query<
-        (Entity, &mut Boid, &mut Transform, Option<&BoidTestingUnit>),
-        Without<BoidPredator>,
-    >,
-    mut boid_predators: Query<
-        (Entity, &mut Boid, &mut Transform),
-        (With<BoidPredator>, Without<BoidTestingUnit>),
-    >,
+    mut boids: Query<(
+        Entity,
+        &mut Boid,
+        &mut Transform,
+        Has<BoidPredator>,
+        Option<&BoidTestingUnit>,
+        Option<&SteeringBody>,
+        Option<&Species>,
+        Option<&BoidParams>,
+        Option<(&Predation, &PredatorBehaviour)>,
+        SteeringBehaviours,
+    )>,
     wind_currents: Query<&WindCurrent>,
     force_fields: Query<(&Transform, &ForceField), Without<Boid>>,
+    obstacles: Query<(&Transform, &Obstacle), Without<Boid>>,
+    refuges: Query<(&Transform, &Refuge), Without<Boid>>,
+    boid_rules: Res<BoidRules>,
     boid_configuration: Res<BoidConfiguration>,
     simulation_configuration: Res<SimulationConfiguration>,
-    spatial_grid: Res<SpatialGrid>,
+    world_bounds: Res<WorldBounds>,
+    spatial_index: Res<BoidSpatialIndex>,
+    steering_targets: Res<SteeringTargets>,
+    interactions: Res<InteractionMatrix>,
     time: Res<Time>,
 ) {
-    boids
-        .par_iter_mut()
-        .for_each(|(entity, mut boid, mut transform, testing_unit)| {
+    let spatial_index = &**spatial_index;
+    let wind_currents = wind_currents.iter().cloned().collect_vec();
+    let force_fields = force_fields
+        .iter()
+        .map(|(transform, ff)| (transform.translation.xy(), ff.clone()))
+        .collect_vec();
+    let obstacles = obstacles
+        .iter()
+        .map(|(transform, obstacle)| (obstacle.isometry(transform), obstacle.clone()))
+        .collect_vec();
+    let refuges = refuges
+        .iter()
+        .map(|(transform, refuge)| (refuge.isometry(transform), refuge.clone()))
+        .collect_vec();
+    let delta_secs = time.delta_secs();
+    if delta_secs <= 0.0 {
+        return;
+    }
+    let steering_model = simulation_configuration.steering_model;
+    let prioritised_rules = boid_rules.steering_by_priority().collect_vec();
+    let integrator = simulation_configuration.integrator;
+    let substeps = simulation_configuration.substeps.max(1);
+    let step = delta_secs / substeps as f32;
+    boids.par_iter_mut().for_each_init(
+        || (Vec::new(), None),
+        |(neighbourhood, overridden_configuration),
+         (
+            entity,
+            mut boid,
+            mut transform,
+            predator,
+            testing_unit,
+            body,
+            species,
+            boid_params,
+            predation,
+            mut behaviours,
+        )| {
+            let predator_state =
+                predation.map_or(PredatorState::default(), |(_, behaviour)| behaviour.state);
+            // Sprinting raises the top speed of whatever configuration the predator has
+            let sprint_params;
+            let boid_params = if predator_state == PredatorState::Attack {
+                let max_speed = boid_params
+                    .and_then(|boid_params| boid_params.max_speed)
+                    .unwrap_or(boid_configuration.max_speed);
+                sprint_params = boid_params.cloned().unwrap_or_default().with_max_speed(
+                    max_speed
+                        * simulation_configuration
+                            .predator_behaviour
+                            .sprint_multiplier,
+                );
+                Some(&sprint_params)
+            } else {
+                boid_params
+            };
+            let boid_configuration =
+                boid_configuration.resolve(boid_params, overridden_configuration);
+            let perception_radius = boid_configuration.perception_radius();
+            let field_of_view = FieldOfView::from_configuration(boid_configuration);
             let Transform {
                 translation,
                 rotation,
                 scale,
             } = &mut *transform;
-            let position = translation.xy();
-            let mut velocity = Vec2::ZERO;
-            let mut offset_velocity = Vec2::ZERO;
-
-            if testing_unit.is_none()
-                || testing_unit.is_some_and(|testing_unit| testing_unit.follow_boids)
-            {
-                // Common
-                let mut perceived_centre = OVec2::default();
-                let mut perceived_velocity = OVec2::default();
-                let mut neighbours_to_follow = 0;
-                let mut push_force = Vec2::ZERO;
-                let view_radius = boid_configuration.scalar_parametre("Radio de visión");
-                let view_radius_squared = view_radius.squared();
-                let avoidance_radius = boid_configuration.scalar_parametre("Radio de separación");
-                let avoidance_radius_squared = avoidance_radius.squared();
-                for cell in spatial_grid.iter_radius(position, view_radius) {
-                    for other_boid in cell
-                        .cell_boids()
-                        .iter()
-                        .filter(|cell_boid| cell_boid.entity != entity)
-                    {
-                        let distance_squared = position.distance_squared(other_boid.position);
-                        let r = other_boid.position - position;
-                        if boid_predators.contains(other_boid.entity) {
-                            if distance_squared < view_radius_squared {
-                                push_force -= boid_configuration.scalar_parametre("Peso de huida")
-                                    * r.normalize_or_zero()
-                                    * boid.speed;
-                            }
-                        } else {
-                            if distance_squared < avoidance_radius_squared {
-                                push_force -= (boid_configuration
-                                    .scalar_parametre("Peso de separación")
-                                    * avoidance_radius_squared
-                                    * r.normalize_or(boid.velocity())
-                                    / if distance_squared < 0.1 {
-                                        1.0
-                                    } else {
-                                        distance_squared
-                                    })
-                                .clamp_length_max(avoidance_radius_squared);
-                            } else if distance_squared < view_radius_squared {
-                                perceived_centre += other_boid.position;
-                                perceived_velocity += other_boid.velocity;
-                                neighbours_to_follow += 1;
-                            }
-                        }
+            let body = body.copied().unwrap_or_default();
+            let follow_boids = testing_unit.is_none_or(|testing_unit| testing_unit.follow_boids);
+            let mut position = translation.xy();
+            let base = BoidRuleParametres {
+                entity,
+                position,
+                velocity: boid.velocity(),
+                speed: boid.speed,
+                predator,
+                satiated: predation.is_some_and(|(predation, _)| predation.is_satiated()),
+                predator_state,
+                predator_target: predation.and_then(|(_, behaviour)| behaviour.target),
+                predator_waypoint: predation.and_then(|(_, behaviour)| behaviour.waypoint),
+                perception_radius,
+                field_of_view,
+                neighbourhood: &[],
+                spatial_index,
+                force_fields: &force_fields,
+                obstacles: &obstacles,
+                refuges: &refuges,
+                species: species.map_or(0, |species| species.0),
+                interactions: &interactions,
+                wind_currents: &wind_currents,
+                simulation_configuration: &simulation_configuration,
+                world_bounds: &world_bounds,
+            };
+            // Rules yield per-tick velocity changes; the Reynolds model reads them as the force
+            // that would produce them on a unit mass
+            behaviours.advance(delta_secs);
+            let behaviours = &behaviours;
+            // Field rules read `field_params`, which integrators may move along the step
+            let steering = |params: BoidRuleParametres<'_>,
+                            field_params: BoidRuleParametres<'_>| {
+                let apply_rule = |rule: &BoidRule| match rule.kind() {
+                    RuleKind::Field => rule.apply(field_params, boid_configuration),
+                    _ => rule.apply(params, boid_configuration),
+                };
+                match steering_model {
+                    SteeringModel::Direct => {
+                        let mut steering = boid_rules.steering().map(apply_rule).sum::<Vec2>();
+                        behaviours.steer(
+                            params,
+                            boid_configuration,
+                            &steering_targets,
+                            |_, force| steering += force,
+                        );
+                        steering
+                    }
+                    SteeringModel::Reynolds if behaviours.is_empty() => body.allocate(
+                        prioritised_rules
+                            .iter()
+                            .map(|rule| (rule.priority(), apply_rule(rule) / delta_secs)),
+                    ),
+                    SteeringModel::Reynolds => {
+                        let mut forces = prioritised_rules
+                            .iter()
+                            .map(|rule| (rule.priority(), apply_rule(rule)))
+                            .collect_vec();
+                        behaviours.steer(
+                            params,
+                            boid_configuration,
+                            &steering_targets,
+                            |priority, force| forces.push((priority, force)),
+                        );
+                        forces.sort_by_key(|(priority, _)| *priority);
+                        body.allocate(
+                            forces
+                                .into_iter()
+                                .map(|(priority, force)| (priority, force / delta_secs)),
+                        )
                     }
                 }
-                if neighbours_to_follow > 1 {
-                    let neighbours_to_follow = neighbours_to_follow as f32;
-                    perceived_centre /= neighbours_to_follow;
-                    perceived_velocity /= neighbours_to_follow;
+            };
+            let apply = |boid: &mut Boid, steering: Vec2, step: f32| match steering_model {
+                SteeringModel::Direct => {
+                    boid.add_velocity(steering * step / delta_secs, boid_configuration)
                 }
+                SteeringModel::Reynolds => boid.steer(steering, &body, boid_configuration, step),
+            };
+            let drift = |params: BoidRuleParametres<'_>| {
+                boid_rules
+                    .drift()
+                    .map(|rule| rule.apply(params, boid_configuration))
+                    .sum::<Vec2>()
+            };
 
-                // Force fields
-                for (ff_point, ff) in force_fields {
-                    let point = ff_point.translation.xy();
-                    let distance = point.distance(position);
-                    let charge = ff.charge.abs();
-                    push_force += if distance <= charge {
-                        ff.charge.signum()
-                            * (position - point).normalize_or(boid.velocity().normalize())
-                            * boid.speed
-                            * (1.0 - (1.0 - (distance / charge - 1.0).squared()).sqrt())
-                    } else {
-                        Vec2::ZERO
-                    };
+            for _ in 0..substeps {
+                if !follow_boids {
+                    apply(&mut boid, Vec2::ZERO, step);
+                    position += boid.velocity() * step;
+                    continue;
                 }
-
-                // Cohesion
-                velocity += (perceived_centre.get().unwrap_or(position) - position)
-                    * boid_configuration.scalar_parametre("Peso de cohesión");
-
-                // Separation
-                velocity += push_force;
-
-                // Alignment
-                velocity += (perceived_velocity.get().unwrap_or(velocity) - velocity)
-                    * boid_configuration.scalar_parametre("Peso de alineamiento");
-
-                // Strong wind
-                offset_velocity += Vec2::from_angle(
-                    boid_configuration
-                        .scalar_parametre("Ángulo del viento")
-                        .to_radians(),
-                ) * boid_configuration.scalar_parametre("Velocidad del viento");
-
-                // Wind currents
-                for wind_current in wind_currents {
-                    if let Some((t, _, _)) = wind_current.closest(position) {
-                        let curve = wind_current.curve();
-                        offset_velocity +=
-                            curve.velocity(t).normalize_or_zero() * wind_current.wind_speed;
+                gather_neighbourhood(
+                    spatial_index,
+                    &simulation_configuration,
+                    entity,
+                    position,
+                    perception_radius,
+                    neighbourhood,
+                );
+                let params = BoidRuleParametres {
+                    position,
+                    velocity: boid.velocity(),
+                    speed: boid.speed,
+                    neighbourhood,
+                    ..base
+                };
+                let current_steering = steering(params, params);
+                let drift_velocity = drift(params);
+                match integrator {
+                    Integrator::ExplicitEuler => {
+                        position += (boid.velocity() + drift_velocity) * step;
+                        apply(&mut boid, current_steering, step);
+                    }
+                    Integrator::SemiImplicitEuler => {
+                        apply(&mut boid, current_steering, step);
+                        position += (boid.velocity() + drift_velocity) * step;
+                    }
+                    Integrator::VelocityVerlet => {
+                        let mut predicted = *boid;
+                        apply(&mut predicted, current_steering, step);
+                        let next_position = position
+                            + ((boid.velocity() + predicted.velocity()) / 2.0 + drift_velocity)
+                                * step;
+                        gather_neighbourhood(
+                            spatial_index,
+                            &simulation_configuration,
+                            entity,
+                            next_position,
+                            perception_radius,
+                            neighbourhood,
+                        );
+                        let next_params = BoidRuleParametres {
+                            position: next_position,
+                            velocity: predicted.velocity(),
+                            speed: predicted.speed,
+                            neighbourhood,
+                            ..base
+                        };
+                        let next_steering = steering(next_params, next_params);
+                        apply(&mut boid, (current_steering + next_steering) / 2.0, step);
+                        position = next_position;
+                    }
+                    // The neighbourhood is held for the whole step, while the field and drift terms,
+                    // which depend on position alone, are sampled again at every stage
+                    Integrator::RungeKutta4 => {
+                        let stage = |previous: Vec2, step: f32, position: Vec2| {
+                            let mut stage = *boid;
+                            apply(&mut stage, previous, step);
+                            let stage_params = BoidRuleParametres {
+                                position,
+                                velocity: stage.velocity(),
+                                speed: stage.speed,
+                                ..params
+                            };
+                            (
+                                steering(params, stage_params),
+                                stage.velocity() + drift(stage_params),
+                            )
+                        };
+                        let (s1, k1) = (current_steering, boid.velocity() + drift_velocity);
+                        let (s2, k2) = stage(s1, step / 2.0, position + k1 * step / 2.0);
+                        let (s3, k3) = stage(s2, step / 2.0, position + k2 * step / 2.0);
+                        let (s4, k4) = stage(s3, step, position + k3 * step);
+                        apply(&mut boid, (s1 + 2.0 * s2 + 2.0 * s3 + s4) / 6.0, step);
+                        position += (k1 + 2.0 * k2 + 2.0 * k3 + k4) / 6.0 * step;
                     }
                 }
             }
-
-            boid.add_velocity(velocity, &boid_configuration);
-            *translation += (boid.velocity() + offset_velocity).extend(0.0) * time.delta_secs();
+            *translation = position.extend(translation.z);
             *rotation = Quat::from_axis_angle(Vec3::Z, boid.angle);
             *scale = Vec2::splat(boid_configuration.scale).extend(1.0);
+        },
+    );
+}
+
+fn gather_neighbourhood<'a>(
+    spatial_index: &'a dyn SpatialIndex,
+    simulation_configuration: &SimulationConfiguration,
+    entity: Entity,
+    position: Vec2,
+    perception_radius: f32,
+    neighbourhood: &mut Vec<Neighbour<'a>>,
+) {
+    neighbourhood.clear();
+    match simulation_configuration.neighbourhood_mode {
+        NeighbourhoodMode::Metric => spatial_index.neighbours_within(
+            position,
+            perception_radius,
+            Some(entity),
+            neighbourhood,
+        ),
+        NeighbourhoodMode::Topological => spatial_index.k_nearest(
+            position,
+            simulation_configuration.topological_neighbours,
+            Some(entity),
+            neighbourhood,
+        ),
+        NeighbourhoodMode::Hybrid => {
+            spatial_index.neighbours_within(
+                position,
+                perception_radius,
+                Some(entity),
+                neighbourhood,
+            );
+            sort_and_truncate(
+                neighbourhood,
+                simulation_configuration.topological_neighbours,
+            );
+        }
+    }
+}
+
+pub fn move_obstacles(
+    obstacles: Query<(&mut Transform, &mut Obstacle)>,
+    world_bounds: Res<WorldBounds>,
+    simulation_configuration: Res<SimulationConfiguration>,
+    time: Res<Time>,
+) {
+    let Rect { min, max } = world_bounds.rect;
+    for (mut transform, mut obstacle) in obstacles {
+        if obstacle.velocity == Vec2::ZERO {
+            continue;
+        }
+        transform.translation += (obstacle.velocity * time.delta_secs()).extend(0.0);
+        let Vec3 { x, y, .. } = &mut transform.translation;
+        if simulation_configuration.boundary_mode == BoundaryMode::Wrap {
+            x.toroidal_clamp(min.x, max.x);
+            y.toroidal_clamp(min.y, max.y);
+        } else {
+            if (*x < min.x && obstacle.velocity.x < 0.0)
+                || (*x > max.x && obstacle.velocity.x > 0.0)
+            {
+                obstacle.velocity.x = -obstacle.velocity.x;
+            }
+            if (*y < min.y && obstacle.velocity.y < 0.0)
+                || (*y > max.y && obstacle.velocity.y > 0.0)
+            {
+                obstacle.velocity.y = -obstacle.velocity.y;
+            }
+        }
+    }
+}
+
+pub fn resolve_obstacle_collisions(
+    mut boids: Query<(
+        &mut Transform,
+        &mut Boid,
+        Option<&BoidParams>,
+        Has<BoidPredator>,
+    )>,
+    obstacles: Query<(&Transform, &Obstacle), Without<Boid>>,
+    refuges: Query<(&Transform, &Refuge), Without<Boid>>,
+    boid_configuration: Res<BoidConfiguration>,
+    time: Res<Time>,
+) {
+    if obstacles.is_empty() && refuges.is_empty() {
+        return;
+    }
+    let obstacles = obstacles
+        .iter()
+        .map(|(transform, obstacle)| (obstacle.isometry(transform), obstacle))
+        .collect_vec();
+    let closed_refuges = refuges
+        .iter()
+        .filter(|(_, refuge)| refuge.policy == RefugePolicy::NoEntry)
+        .map(|(transform, refuge)| (refuge.isometry(transform), refuge))
+        .collect_vec();
+    boids.par_iter_mut().for_each_init(
+        || None,
+        |overridden_configuration, (mut transform, mut boid, boid_params, predator)| {
+            let boid_configuration =
+                boid_configuration.resolve(boid_params, overridden_configuration);
+            let radius = Obstacle::BOID_RADIUS * boid_configuration.scale;
+            let mut position = transform.translation.xy();
+            let mut velocity = boid.velocity();
+            let mut touched = false;
+            for &(isometry, obstacle) in &obstacles {
+                let previous = position - (velocity - obstacle.velocity) * time.delta_secs();
+                let (distance, normal) = obstacle.signed_distance(isometry, position);
+                let (depth, normal) = match obstacle.crossed(isometry, previous, position) {
+                    Some(normal) => (distance + radius, normal),
+                    None if distance < radius => (radius - distance, normal),
+                    None => continue,
+                };
+                position += normal * depth;
+                let approach = (velocity - obstacle.velocity).dot(normal);
+                if approach < 0.0 {
+                    velocity -= normal * approach;
+                }
+                touched = true;
+            }
+            // Closed refuges are solid for predators only
+            for &(isometry, refuge) in closed_refuges.iter().filter(|_| predator) {
+                let (distance, normal) = refuge.signed_distance(isometry, position);
+                if distance >= radius {
+                    continue;
+                }
+                position += normal * (radius - distance);
+                let approach = velocity.dot(normal);
+                if approach < 0.0 {
+                    velocity -= normal * approach;
+                }
+                touched = true;
+            }
+            if touched {
+                transform.translation = position.extend(transform.translation.z);
+                boid.set_velocity(velocity, boid_configuration);
+            }
+        },
+    );
+}
+
+pub fn update_predator_targets(
+    mut predators: Query<
+        (
+            Entity,
+            &Transform,
+            &Boid,
+            &Predation,
+            &mut PredatorBehaviour,
+            Option<&Species>,
+            Option<&BoidParams>,
+        ),
+        With<BoidPredator>,
+    >,
+    refuges: Query<(&Transform, &Refuge)>,
+    testing_units: Query<(), With<BoidTestingUnit>>,
+    spatial_index: Res<BoidSpatialIndex>,
+    interactions: Res<InteractionMatrix>,
+    boid_configuration: Res<BoidConfiguration>,
+    simulation_configuration: Res<SimulationConfiguration>,
+    time: Res<Time>,
+) {
+    let settings = &simulation_configuration.predator_behaviour;
+    let delta_secs = time.delta_secs();
+    let refuges = refuges
+        .iter()
+        .map(|(transform, refuge)| (refuge.isometry(transform), refuge))
+        .collect_vec();
+    let mut rng = rand::rng();
+    let mut visible = Vec::new();
+    let mut candidates = Vec::new();
+    let mut overridden_configuration = None;
+    for (predator, transform, boid, predation, mut behaviour, species, boid_params) in
+        &mut predators
+    {
+        behaviour.time_in_state += delta_secs;
+        behaviour.waypoint = None;
+        behaviour.hold_attack = false;
+        let position = transform.translation.xy();
+        let species = species.map_or(0, |species| species.0);
+        visible.clear();
+        spatial_index.neighbours_within(
+            position,
+            settings.detection_radius,
+            Some(predator),
+            &mut visible,
+        );
+        // Prey behind the predator or in its blind spot go unnoticed
+        let field_of_view = FieldOfView::from_configuration(
+            boid_configuration.resolve(boid_params, &mut overridden_configuration),
+        );
+        let heading = boid.velocity().normalize_or_zero();
+        visible.retain(|other| {
+            !other.predator
+                && field_of_view.weight(heading, other.offset, settings.detection_radius) > 0.0
         });
-    boid_predators
-        .par_iter_mut()
-        .for_each(|(entity, mut boid, mut transform)| {
-            let Transform {
-                translation,
-                rotation,
-                scale,
-            } = &mut *transform;
-            let position = translation.xy();
-            let mut velocity = Vec2::ZERO;
-            let mut offset_velocity = Vec2::ZERO;
-
-            let mut push_force = Vec2::ZERO;
-            let mut closest = None;
-            let view_radius = boid_configuration.scalar_parametre("Radio de visión");
-            for cell in spatial_grid.iter_radius(position, view_radius) {
-                for other_boid in cell
-                    .cell_boids()
+        candidates.clear();
+        // The testing boid is never hunted so that it stays available in the inspector
+        candidates.extend(visible.iter().copied().filter(|candidate| {
+            !testing_units.contains(candidate.entity)
+                && interactions.interaction(species, candidate.species).hunt > 0.0
+                && !refuges
                     .iter()
-                    .filter(|cell_boid| cell_boid.entity != entity)
-                {
-                    let distance = position.distance(other_boid.position);
-                    if boids.contains(other_boid.entity) {
-                        if distance
-                            < view_radius.min(position.distance(closest.unwrap_or(Vec2::MAX)))
-                        {
-                            closest = Some(other_boid.position);
-                        }
-                    } else {
-                        let avoidance_radius_squared = boid_configuration
-                            .scalar_parametre("Radio de separación")
-                            .squared();
-                        let distance_squared = distance.squared();
-                        let r = other_boid.position - position;
-                        if distance_squared < avoidance_radius_squared {
-                            push_force -= (boid_configuration
-                                .scalar_parametre("Peso de separación")
-                                * avoidance_radius_squared
-                                * r.normalize_or(boid.velocity())
-                                / if distance_squared < 0.1 {
-                                    1.0
-                                } else {
-                                    distance_squared
-                                })
-                            .clamp_length_max(avoidance_radius_squared);
-                        }
-                    }
+                    .any(|(isometry, refuge)| refuge.contains(*isometry, candidate.position))
+        }));
+        // Crowding as the predator sees it, so only visible prey count
+        let isolation_radius_squared = settings.isolation_radius * settings.isolation_radius;
+        let mut prey_nearby = |prey: &SpatialGridBoid| {
+            let prey_offset = spatial_index.offset(position, prey.position);
+            visible
+                .iter()
+                .filter(|other| {
+                    other.entity != prey.entity
+                        && other.offset.distance_squared(prey_offset) < isolation_radius_squared
+                })
+                .count()
+        };
+        // Rates per second turned into chances for this tick
+        let chance = |rate: f32| 1.0 - (-rate * delta_secs).exp();
+
+        let hunting = behaviour.state != PredatorState::Rest && !predation.is_satiated();
+        let mut target = behaviour
+            .target
+            .filter(|_| hunting)
+            .and_then(|target| {
+                candidates
+                    .iter()
+                    .find(|candidate| candidate.entity == target.entity)
+            })
+            .map(|candidate| candidate.boid);
+        if let Some(locked) = target {
+            if settings.targeting == TargetingStrategy::Confusion {
+                let lock = settings.lock_probability(prey_nearby(locked));
+                let loss = (1.0 - lock) * PredatorBehaviourConfiguration::TARGET_LOCK_RATE;
+                if rng.random_bool(chance(loss) as f64) {
+                    target = None;
                 }
             }
-            // Force fields
-            for (ff_point, ff) in force_fields {
-                let point = ff_point.translation.xy();
-                let distance = point.distance(position);
-                let charge = ff.charge.abs();
-                push_force += if distance <= charge {
-                    ff.charge.signum()
-                        * (position - point).normalize_or(boid.velocity().normalize())
-                        * boid.speed
-                        * (1.0 - (1.0 - (distance / charge - 1.0).squared()).sqrt())
-                } else {
-                    Vec2::ZERO
-                };
+        } else if hunting {
+            target = choose_target(settings, &candidates, &mut prey_nearby);
+            if let Some(chosen) =
+                target.filter(|_| settings.targeting == TargetingStrategy::Confusion)
+            {
+                let lock = settings.lock_probability(prey_nearby(chosen));
+                if !rng.random_bool(
+                    chance(lock * PredatorBehaviourConfiguration::TARGET_LOCK_RATE) as f64,
+                ) {
+                    target = None;
+                }
             }
+        }
+        behaviour.target = target.map(|target| PredatorTarget {
+            entity: target.entity,
+            position: target.position,
+            species: target.species,
+        });
+    }
+}
 
-            // Separation
-            velocity += push_force;
+pub fn coordinate_packs(
+    mut predators: Query<(&Transform, &mut PredatorBehaviour, &Pack), With<BoidPredator>>,
+    packs: Res<PackConfiguration>,
+    spatial_index: Res<BoidSpatialIndex>,
+    simulation_configuration: Res<SimulationConfiguration>,
+) {
+    if !simulation_configuration.pack_hunting {
+        return;
+    }
+    let attack_distance = simulation_configuration.predator_behaviour.attack_distance;
+    let mut members = predators.iter_mut().collect_vec();
+    let positions = members
+        .iter()
+        .map(|(transform, ..)| transform.translation.xy())
+        .collect_vec();
+    for (index, settings) in packs.packs().iter().enumerate() {
+        let pack = (0..members.len())
+            .filter(|&i| members[i].2 .0 == index)
+            .collect_vec();
 
-            // Hunt
-            velocity += {
-                let current_velocity = boid.velocity();
-                simulation_configuration.predator_hunt_weight
-                    * if let Some(closest) = closest {
-                        (closest - position).normalize_or(current_velocity) * boid.speed
-                    } else {
-                        current_velocity
+        // Everyone hunts whatever prey was spotted from the closest
+        if settings.share_targets {
+            let shared = pack
+                .iter()
+                .filter(|&&i| members[i].1.state != PredatorState::Rest)
+                .filter_map(|&i| {
+                    let target = members[i].1.target?;
+                    let distance = spatial_index
+                        .offset(positions[i], target.position)
+                        .length_squared();
+                    Some((target, distance))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(target, _)| target);
+            if let Some(shared) = shared {
+                for &i in &pack {
+                    if members[i].1.state != PredatorState::Rest {
+                        members[i].1.target = Some(shared);
                     }
-            };
+                }
+            }
+        }
 
-            // Strong wind
-            offset_velocity += Vec2::from_angle(
-                boid_configuration
-                    .scalar_parametre("Ángulo del viento")
-                    .to_radians(),
-            ) * boid_configuration.scalar_parametre("Velocidad del viento");
-
-            // Wind currents
-            for wind_current in wind_currents {
-                if let Some((t, _, _)) = wind_current.closest(position) {
-                    let curve = wind_current.curve();
-                    offset_velocity +=
-                        curve.velocity(t).normalize_or_zero() * wind_current.wind_speed;
+        // Stalking members grouped by their prey
+        let mut stalkers = pack
+            .iter()
+            .filter(|&&i| members[i].1.state == PredatorState::Stalk)
+            .filter_map(|&i| Some((members[i].1.target?, i)))
+            .collect_vec();
+        stalkers.sort_by_key(|(target, _)| target.entity);
+        for group in stalkers.chunk_by(|(a, _), (b, _)| a.entity == b.entity) {
+            let target = group[0].0;
+            // Slots are dealt in angular order so that members do not cross each other
+            let angle = |i: usize| {
+                spatial_index
+                    .offset(target.position, positions[i])
+                    .to_angle()
+            };
+            let mut order = group.iter().map(|&(_, i)| i).collect_vec();
+            order.sort_by(|&a, &b| angle(a).total_cmp(&angle(b)));
+            let base = angle(order[0]);
+            let mut ready = 0;
+            for (rank, &i) in order.iter().enumerate() {
+                let waypoint = settings.encircle.then(|| {
+                    let slot = base + f32::consts::TAU * rank as f32 / order.len() as f32;
+                    target.position + Vec2::from_angle(slot) * settings.encircle_radius
+                });
+                let in_position = match waypoint {
+                    Some(waypoint) => {
+                        spatial_index.offset(positions[i], waypoint).length()
+                            <= settings.encircle_radius * PackSettings::SLOT_TOLERANCE
+                    }
+                    None => {
+                        spatial_index.offset(positions[i], target.position).length()
+                            <= attack_distance
+                    }
+                };
+                ready += in_position as usize;
+                let behaviour = &mut members[i].1;
+                behaviour.waypoint = waypoint;
+                // Encircling members attack from their slot rather than at attack distance
+                behaviour.hold_attack = settings.coordinated_attack || settings.encircle;
+                if !settings.coordinated_attack && settings.encircle && in_position {
+                    behaviour.enter(PredatorState::Attack);
                 }
             }
+            if settings.coordinated_attack
+                && ready as f32 >= settings.attack_quorum * order.len() as f32
+            {
+                for &i in &order {
+                    members[i].1.enter(PredatorState::Attack);
+                }
+            }
+        }
+    }
+}
 
-            boid.add_velocity(velocity, &boid_configuration);
-            *translation += (boid.velocity() + offset_velocity).extend(0.0) * time.delta_secs();
-            *rotation = Quat::from_axis_angle(Vec3::Z, boid.angle);
-            *scale = Vec2::splat(boid_configuration.scale).extend(1.0);
+pub fn update_predator_states(
+    mut predators: Query<(&Transform, &Predation, &mut PredatorBehaviour), With<BoidPredator>>,
+    spatial_index: Res<BoidSpatialIndex>,
+    simulation_configuration: Res<SimulationConfiguration>,
+) {
+    let settings = &simulation_configuration.predator_behaviour;
+    for (transform, predation, mut behaviour) in &mut predators {
+        let position = transform.translation.xy();
+        let distance = behaviour
+            .target
+            .map(|target| spatial_index.offset(position, target.position).length());
+        let next = match behaviour.state {
+            PredatorState::Patrol if distance.is_some() => Some(PredatorState::Stalk),
+            PredatorState::Stalk if distance.is_none() => Some(PredatorState::Patrol),
+            PredatorState::Stalk if behaviour.time_in_state >= settings.stalk_time => {
+                Some(PredatorState::Rest)
+            }
+            PredatorState::Stalk
+                if !behaviour.hold_attack
+                    && distance.is_some_and(|distance| distance <= settings.attack_distance) =>
+            {
+                Some(PredatorState::Attack)
+            }
+            PredatorState::Attack
+                if distance.is_none() || behaviour.time_in_state >= settings.attack_time =>
+            {
+                Some(PredatorState::Rest)
+            }
+            PredatorState::Rest
+                if behaviour.time_in_state >= settings.rest_time && !predation.is_satiated() =>
+            {
+                Some(PredatorState::Patrol)
+            }
+            _ => None,
+        };
+        if let Some(next) = next {
+            behaviour.enter(next);
+        }
+    }
+}
+
+fn choose_target<'a>(
+    settings: &PredatorBehaviourConfiguration,
+    candidates: &[Neighbour<'a>],
+    prey_nearby: &mut impl FnMut(&SpatialGridBoid) -> usize,
+) -> Option<&'a SpatialGridBoid> {
+    let nearest = |candidates: &[Neighbour<'a>]| {
+        candidates
+            .iter()
+            .min_by(|a, b| a.distance_squared.total_cmp(&b.distance_squared))
+            .map(|candidate| candidate.boid)
+    };
+    // Offsets are taken from the predator so the centroid survives wrapping around the world
+    let centroid_offset = || {
+        candidates
+            .iter()
+            .map(|candidate| candidate.offset)
+            .sum::<Vec2>()
+            / candidates.len().max(1) as f32
+    };
+    let target = match settings.targeting {
+        TargetingStrategy::Nearest | TargetingStrategy::Confusion => nearest(candidates)?,
+        TargetingStrategy::MostIsolated => {
+            candidates
+                .iter()
+                .map(|candidate| {
+                    (
+                        (prey_nearby(candidate.boid), candidate.distance_squared),
+                        candidate,
+                    )
+                })
+                .min_by(|((a_crowd, a_distance), _), ((b_crowd, b_distance), _)| {
+                    a_crowd.cmp(b_crowd).then(a_distance.total_cmp(b_distance))
+                })?
+                .1
+                .boid
+        }
+        TargetingStrategy::FlockCentroid => {
+            let centroid = centroid_offset();
+            candidates
+                .iter()
+                .min_by(|a, b| {
+                    a.offset
+                        .distance_squared(centroid)
+                        .total_cmp(&b.offset.distance_squared(centroid))
+                })?
+                .boid
+        }
+        TargetingStrategy::Peripheral => {
+            let centroid = centroid_offset();
+            candidates
+                .iter()
+                .max_by(|a, b| {
+                    a.offset
+                        .distance_squared(centroid)
+                        .total_cmp(&b.offset.distance_squared(centroid))
+                })?
+                .boid
+        }
+    };
+    Some(target)
+}
+
+pub fn capture_prey(
+    mut commands: Commands,
+    mut predators: Query<
+        (Entity, &Transform, &mut Predation, &mut PredatorBehaviour),
+        With<BoidPredator>,
+    >,
+    mut prey: Query<(&mut Transform, &mut Boid), Without<BoidPredator>>,
+    refuges: Query<(&Transform, &Refuge), Without<Boid>>,
+    spatial_index: Res<BoidSpatialIndex>,
+    simulation_configuration: Res<SimulationConfiguration>,
+    world_bounds: Res<WorldBounds>,
+    mut statistics: ResMut<CaptureStatistics>,
+    mut prey_captured: EventWriter<PreyCaptured>,
+    time: Res<Time>,
+) {
+    let capture_radius = simulation_configuration.capture_radius;
+    let mut rng = rand::rng();
+    let mut captured = Vec::new();
+    for (predator, transform, mut predation, mut behaviour) in &mut predators {
+        predation.tick(time.delta_secs());
+        if behaviour.state != PredatorState::Attack || !predation.can_strike() {
+            continue;
+        }
+        // Only the locked prey can be struck
+        let Some(target) = behaviour
+            .target
+            .map(|target| target.entity)
+            .filter(|target| !captured.contains(target))
+        else {
+            continue;
+        };
+        let Ok((mut prey_transform, mut prey_boid)) = prey.get_mut(target) else {
+            continue;
+        };
+        let distance = spatial_index
+            .offset(transform.translation.xy(), prey_transform.translation.xy())
+            .length();
+        let prey_position = prey_transform.translation.xy();
+        if distance > capture_radius
+            || refuges.iter().any(|(transform, refuge)| {
+                refuge.contains(refuge.isometry(transform), prey_position)
+            })
+        {
+            continue;
+        }
+
+        predation.attempts += 1;
+        statistics.attempts += 1;
+        if !rng.random_bool(simulation_configuration.capture_probability.clamp(0.0, 1.0) as f64) {
+            predation.strike_cooldown = Predation::MISS_COOLDOWN;
+            continue;
+        }
+        predation.captures += 1;
+        predation.satiation = simulation_configuration.handling_time;
+        behaviour.enter(PredatorState::Rest);
+        behaviour.target = None;
+        statistics.captures += 1;
+        captured.push(target);
+        prey_captured.write(PreyCaptured {
+            predator,
+            prey: target,
+            position: prey_transform.translation.xy(),
         });
+        match simulation_configuration.capture_outcome {
+            CaptureOutcome::Despawn => commands.entity(target).despawn(),
+            CaptureOutcome::Respawn => {
+                let Rect { min, max } = world_bounds.rect;
+                let pi = f32::consts::PI;
+                prey_boid.angle = rng.random_range(-pi..=pi);
+                prey_transform.translation = Vec2::new(
+                    rng.random_range(min.x..=max.x),
+                    rng.random_range(min.y..=max.y),
+                )
+                .extend(prey_transform.translation.z);
+                prey_transform.rotation = Quat::from_axis_angle(Vec3::Z, prey_boid.angle);
+            }
+        }
+    }
 }
 
-pub fn wrap_edges(boids: Query<&mut Transform, With<Boid>>, spatial_grid: Res<SpatialGrid>) {
-    for mut transform in boids {
-        let safe_offset = Vec2::splat(0.1f32);
-        let bounds = spatial_grid.grid_size() / 2.0;
-        let Vec2 { x: bx, y: by } = bounds - safe_offset;
-        let Vec3 { x, y, .. } = &mut transform.translation;
-        x.toroidal_clamp(-bx, bx);
-        y.toroidal_clamp(-by, by);
+pub fn apply_boundaries(
+    mut commands: Commands,
+    boids: Query<(Entity, &mut Transform, &mut Boid)>,
+    world_bounds: Res<WorldBounds>,
+    simulation_configuration: Res<SimulationConfiguration>,
+) {
+    let safe_offset = Vec2::splat(0.1f32);
+    let min = world_bounds.rect.min + safe_offset;
+    let max = world_bounds.rect.max - safe_offset;
+    let inside = Rect::from_corners(min, max);
+    let mut rng = rand::rng();
+    for (entity, mut transform, mut boid) in boids {
+        let position = transform.translation.xy();
+        match simulation_configuration.boundary_mode {
+            BoundaryMode::Wrap => {
+                let Vec3 { x, y, .. } = &mut transform.translation;
+                x.toroidal_clamp(min.x, max.x);
+                y.toroidal_clamp(min.y, max.y);
+            }
+            BoundaryMode::Reflect => {
+                if inside.contains(position) {
+                    continue;
+                }
+                let mut velocity = boid.velocity();
+                if position.x < min.x {
+                    velocity.x = velocity.x.abs();
+                } else if position.x > max.x {
+                    velocity.x = -velocity.x.abs();
+                }
+                if position.y < min.y {
+                    velocity.y = velocity.y.abs();
+                } else if position.y > max.y {
+                    velocity.y = -velocity.y.abs();
+                }
+                boid.angle = velocity.to_angle();
+                transform.translation = position.clamp(min, max).extend(transform.translation.z);
+                transform.rotation = Quat::from_axis_angle(Vec3::Z, boid.angle);
+            }
+            BoundaryMode::SteerBack => {
+                transform.translation = position.clamp(min, max).extend(transform.translation.z);
+            }
+            BoundaryMode::Despawn => {
+                if !inside.contains(position) {
+                    commands.entity(entity).despawn();
+                }
+            }
+            BoundaryMode::Respawn => {
+                if !inside.contains(position) {
+                    let pi = f32::consts::PI;
+                    boid.angle = rng.random_range(-pi..=pi);
+                    transform.translation = Vec2::new(
+                        rng.random_range(min.x..=max.x),
+                        rng.random_range(min.y..=max.y),
+                    )
+                    .extend(transform.translation.z);
+                    transform.rotation = Quat::from_axis_angle(Vec3::Z, boid.angle);
+                }
+            }
+        }
+    }
+}
+
+pub fn colour_predators(
+    mut predators: Query<(&PredatorBehaviour, &mut Sprite, Option<&Species>), With<BoidPredator>>,
+    interactions: Res<InteractionMatrix>,
+) {
+    for (behaviour, mut sprite, species) in &mut predators {
+        let colour = behaviour.state.colour().unwrap_or_else(|| {
+            interactions
+                .definition(species.copied().unwrap_or_default())
+                .map_or(Color::srgb(1.0, 0.2, 0.2), |definition| definition.colour)
+        });
+        if sprite.color != colour {
+            sprite.color = colour;
+        }
     }
 }
 
 pub fn draw_debug(
     wind_currents: Query<&WindCurrent>,
-    testing_unit_boid: Option<Single<(&Transform, &mut Sprite), With<BoidTestingUnit>>>,
+    testing_unit_boid: Option<
+        Single<(&Transform, &Boid, &mut Sprite, Option<&BoidParams>), With<BoidTestingUnit>>,
+    >,
     force_fields: Query<(&Transform, &ForceField)>,
-    spatial_grid: Res<SpatialGrid>,
+    spatial_index: Res<BoidSpatialIndex>,
+    world_bounds: Res<WorldBounds>,
     boid_configuration: Res<BoidConfiguration>,
     simulation_configuration: Res<SimulationConfiguration>,
     mut gizmos: Gizmos,
@@ -376,38 +1271,62 @@ pub fn draw_debug(
     let Some(boid) = testing_unit_boid else {
         return;
     };
-    let (transform, mut sprite) = boid.into_inner();
+    let (transform, boid, mut sprite, boid_params) = boid.into_inner();
+    let mut overridden_configuration = None;
+    let boid_configuration = boid_configuration.resolve(boid_params, &mut overridden_configuration);
     let position = transform.translation.xy();
     sprite.color = Color::srgb(0.3, 0.3, 1.0);
     gizmos
         .circle_2d(
             position,
-            boid_configuration.scalar_parametre("Radio de separación"),
+            boid_configuration.scalar_parametre(ParamId::SEPARATION_RADIUS),
             RED,
         )
         .resolution(64);
     gizmos
         .circle_2d(
             position,
-            boid_configuration.scalar_parametre("Radio de visión"),
+            boid_configuration.scalar_parametre(ParamId::VIEW_RADIUS),
             GREEN,
         )
         .resolution(64);
-
-    // Spatial grid
-    for cell in spatial_grid.cells() {
-        gizmos.rect_2d(
-            cell.location(),
-            Vec2::splat(spatial_grid.cell_size()),
-            WHITE,
-        );
+    let field_of_view = FieldOfView::from_configuration(boid_configuration);
+    if !field_of_view.is_full_circle() {
+        let view_radius = boid_configuration.scalar_parametre(ParamId::VIEW_RADIUS);
+        let half_angle = field_of_view.visible_half_angle();
+        let start = boid.angle - half_angle;
+        gizmos
+            .arc_2d(
+                Isometry2d::new(position, Rot2::radians(start - f32::consts::FRAC_PI_2)),
+                2.0 * half_angle,
+                view_radius,
+                YELLOW,
+            )
+            .resolution(64);
+        for edge in [start, boid.angle + half_angle] {
+            gizmos.line_2d(
+                position,
+                position + Vec2::from_angle(edge) * view_radius,
+                YELLOW,
+            );
+        }
     }
-    for cell in spatial_grid.iter_radius(
-        position,
-        boid_configuration.scalar_parametre("Radio de visión"),
-    ) {
-        for r in 1..=4 {
-            gizmos.circle_2d(cell.location(), (r * 20) as f32, ORANGE);
+
+    // World bounds
+    gizmos.rect_2d(world_bounds.centre(), world_bounds.size(), GRAY);
+
+    // Spatial index
+    spatial_index.debug_rects(&mut |rect| {
+        gizmos.rect_2d(rect.center(), rect.size(), WHITE);
+    });
+    if let Some(spatial_grid) = spatial_index.as_any().downcast_ref::<SpatialGrid>() {
+        for cell in spatial_grid.iter_radius(
+            position,
+            boid_configuration.scalar_parametre(ParamId::VIEW_RADIUS),
+        ) {
+            for r in 1..=4 {
+                gizmos.circle_2d(cell.location(), (r * 20) as f32, ORANGE);
+            }
         }
     }
 
@@ -446,3 +1365,55 @@ pub fn draw_debug(
         }
     }
 }
+
+// Like obstacles, refuges are always drawn
+pub fn draw_refuges(refuges: Query<(&Transform, &Refuge)>, mut gizmos: Gizmos) {
+    for (transform, refuge) in refuges {
+        let isometry = refuge.isometry(transform);
+        let colour = match refuge.policy {
+            RefugePolicy::NoEntry => LIME,
+            RefugePolicy::NoCapture => YELLOW_GREEN,
+        };
+        match &refuge.shape {
+            RefugeShape::Circle { radius } => {
+                gizmos.circle_2d(isometry, *radius, colour);
+            }
+            RefugeShape::Polygon { vertices } => {
+                gizmos.linestrip_2d(
+                    vertices
+                        .iter()
+                        .chain(vertices.first())
+                        .map(|&vertex| isometry * vertex),
+                    colour,
+                );
+            }
+        }
+    }
+}
+
+// Obstacles have no sprite, so they are drawn even when debug drawing is off
+pub fn draw_obstacles(obstacles: Query<(&Transform, &Obstacle)>, mut gizmos: Gizmos) {
+    for (transform, obstacle) in obstacles {
+        let isometry = obstacle.isometry(transform);
+        match &obstacle.shape {
+            ObstacleShape::Circle { radius } => {
+                gizmos.circle_2d(isometry, *radius, TEAL);
+            }
+            ObstacleShape::Box { half_size } => {
+                gizmos.rect_2d(isometry, *half_size * 2.0, TEAL);
+            }
+            ObstacleShape::Polygon { vertices } => {
+                gizmos.linestrip_2d(
+                    vertices
+                        .iter()
+                        .chain(vertices.first())
+                        .map(|&vertex| isometry * vertex),
+                    TEAL,
+                );
+            }
+            ObstacleShape::Wall { start, end } => {
+                gizmos.line_2d(isometry * *start, isometry * *end, TEAL);
+            }
+        }
+    }
+}
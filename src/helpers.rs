@@ -1,50 +1,88 @@
-use crate::boid_simulation::resources::*;
 use bevy::prelude::*;
 use std::{
     fmt::Debug,
-    ops::{AddAssign, DivAssign, Range, RangeInclusive},
+    ops::{AddAssign, Deref, DivAssign, Range, RangeInclusive},
 };
 
-#[derive(Reflect)]
+#[derive(Clone, Copy, Reflect)]
 pub struct SpatialGridBoid {
     pub entity: Entity,
     pub position: Vec2,
     pub velocity: Vec2,
+    pub predator: bool,
+    pub species: usize,
 }
 
 impl SpatialGridBoid {
-    pub fn new(entity: Entity, position: Vec2, velocity: Vec2) -> Self {
+    pub fn new(entity: Entity, position: Vec2, velocity: Vec2, predator: bool) -> Self {
         Self {
             entity,
             position,
             velocity,
+            predator,
+            species: 0,
         }
     }
+
+    pub fn with_species(mut self, species: usize) -> Self {
+        self.species = species;
+        self
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Neighbour<'g> {
+    pub boid: &'g SpatialGridBoid,
+    pub offset: Vec2,
+    pub distance_squared: f32,
+    pub weight: f32,
+}
+
+impl<'g> Neighbour<'g> {
+    pub fn new(boid: &'g SpatialGridBoid, offset: Vec2) -> Self {
+        Self {
+            boid,
+            offset,
+            distance_squared: offset.length_squared(),
+            weight: 1.0,
+        }
+    }
+
+    pub fn with_weight(self, weight: f32) -> Self {
+        Self { weight, ..self }
+    }
 }
 
-pub(crate) type CellBoids = Vec<SpatialGridBoid>;
-pub(crate) type Cells = Vec<SpatialGridCell>;
+impl Deref for Neighbour<'_> {
+    type Target = SpatialGridBoid;
+
+    fn deref(&self) -> &Self::Target {
+        self.boid
+    }
+}
 
-#[derive(Reflect)]
-pub struct SpatialGridCell {
+#[derive(Clone, Copy)]
+pub struct SpatialGridCell<'g> {
     pub(crate) grid_pos: UVec2,
     pub(crate) rect: Rect,
-    pub(crate) boids: CellBoids,
+    pub(crate) boids: &'g [SpatialGridBoid],
 }
 
-impl SpatialGridCell {
-    pub fn new(row: u32, column: u32, size: f32, centre: Vec2) -> Self {
+impl<'g> SpatialGridCell<'g> {
+    pub fn new(
+        row: u32,
+        column: u32,
+        size: f32,
+        centre: Vec2,
+        boids: &'g [SpatialGridBoid],
+    ) -> Self {
         Self {
-            grid_pos: (row, column).into(),
+            grid_pos: (column, row).into(),
             rect: Rect::from_center_size(centre, Vec2::new(size, size)),
-            boids: Vec::with_capacity(SimulationConfiguration::max_boids() as usize),
+            boids,
         }
     }
 
-    pub fn push(&mut self, boid: SpatialGridBoid) {
-        self.boids.push(boid);
-    }
-
     pub fn size(&self) -> f32 {
         self.rect.size().x
     }
@@ -53,8 +91,12 @@ impl SpatialGridCell {
         self.rect.center()
     }
 
-    pub fn cell_boids(&self) -> &CellBoids {
-        &self.boids
+    pub fn grid_position(&self) -> UVec2 {
+        self.grid_pos
+    }
+
+    pub fn cell_boids(&self) -> &'g [SpatialGridBoid] {
+        self.boids
     }
 
     pub fn contains(&self, location: Vec2) -> bool {
@@ -62,7 +104,7 @@ impl SpatialGridCell {
     }
 }
 
-impl Debug for SpatialGridCell {
+impl Debug for SpatialGridCell<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{} boids", self.boids.len())
     }
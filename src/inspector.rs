@@ -1,10 +1,11 @@
 use crate::{
-    boid_simulation::{components::*, resources::*},
+    boid_simulation::{components::*, resources::*, spatial_index::*},
     states::*,
 };
 use bevy::prelude::*;
 use bevy_egui::*;
 use bevy_inspector_egui::{bevy_inspector::*, DefaultInspectorConfigPlugin};
+use std::ops::RangeInclusive;
 
 #[derive(Default)]
 pub struct InspectorPlugin;
@@ -54,6 +55,18 @@ pub fn inspector_ui(world: &mut World) {
                 egui::Slider::new(&mut boid_config.scale, BoidConfiguration::SCALE_RANGE)
                     .text("Tamaño de los boids"),
             );
+            let mut varied = !boid_config.personality.is_empty();
+            if ui
+                .checkbox(&mut varied, "Personalidades aleatorias al reiniciar")
+                .changed()
+            {
+                let personality = if varied {
+                    Personality::heterogeneous(&boid_config)
+                } else {
+                    Personality::new()
+                };
+                boid_config.personality = personality;
+            }
             ui.label("Parámetros personalizados:");
             for (name, (value, range)) in &mut boid_config {
                 ui.add(egui::Slider::new(value, range.clone()).text(format!(
@@ -80,6 +93,58 @@ pub fn inspector_ui(world: &mut World) {
                     ui.drag_angle(&mut selected_boid.angle);
                 }
             }
+
+            let boid_config = world.resource::<BoidConfiguration>().clone();
+            if let Ok((entity, boid_params)) = world
+                .query_filtered::<(Entity, Option<&BoidParams>), With<BoidTestingUnit>>()
+                .single(world)
+            {
+                let previous = boid_params.cloned().unwrap_or_default();
+                let mut boid_params = previous.clone();
+                ui.label("Parámetros propios:");
+                override_slider(
+                    ui,
+                    &mut boid_params.min_speed,
+                    boid_config.min_speed,
+                    BoidConfiguration::SPEED_RANGE,
+                    "Velocidad mínima",
+                );
+                override_slider(
+                    ui,
+                    &mut boid_params.max_speed,
+                    boid_config.max_speed,
+                    BoidConfiguration::SPEED_RANGE,
+                    "Velocidad máxima",
+                );
+                override_slider(
+                    ui,
+                    &mut boid_params.scale,
+                    boid_config.scale,
+                    BoidConfiguration::SCALE_RANGE,
+                    "Tamaño",
+                );
+                for id in boid_config.param_ids() {
+                    let mut value = boid_params.get(id);
+                    override_slider(
+                        ui,
+                        &mut value,
+                        boid_config.scalar_parametre(id),
+                        boid_config.scalar_range(id).clone(),
+                        boid_config.parametre_name(id),
+                    );
+                    match value {
+                        Some(value) => boid_params.set(id, value),
+                        None => boid_params.clear(id),
+                    }
+                }
+                if boid_params != previous {
+                    if boid_params.is_empty() {
+                        world.entity_mut(entity).remove::<BoidParams>();
+                    } else {
+                        world.entity_mut(entity).insert(boid_params);
+                    }
+                }
+            }
         });
     });
 
@@ -89,6 +154,12 @@ pub fn inspector_ui(world: &mut World) {
                 .query_filtered::<(), With<BoidPredator>>()
                 .query(world)
                 .is_empty();
+            let capture_statistics = *world.resource::<CaptureStatistics>();
+            let predator_states = world
+                .query_filtered::<(Entity, &PredatorBehaviour), With<BoidPredator>>()
+                .iter(world)
+                .map(|(entity, behaviour)| (entity, behaviour.state))
+                .collect::<Vec<_>>();
             let mut simulation_config = world.resource_mut::<SimulationConfiguration>();
             ui.add(
                 egui::Slider::new(
@@ -109,6 +180,253 @@ pub fn inspector_ui(world: &mut World) {
                     egui::Slider::new(&mut simulation_config.predator_hunt_weight, 0.0..=1.0)
                         .text("Peso de atosigamiento"),
                 );
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.capture_radius,
+                        SimulationConfiguration::CAPTURE_RADIUS_RANGE,
+                    )
+                    .text("Radio de captura"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut simulation_config.capture_probability, 0.0..=1.0)
+                        .text("Probabilidad de captura"),
+                );
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.handling_time,
+                        SimulationConfiguration::HANDLING_TIME_RANGE,
+                    )
+                    .text("Tiempo de digestión (s)"),
+                );
+                egui::ComboBox::from_label("Presas capturadas")
+                    .selected_text(simulation_config.capture_outcome.name())
+                    .show_ui(ui, |ui| {
+                        for outcome in CaptureOutcome::ALL {
+                            ui.selectable_value(
+                                &mut simulation_config.capture_outcome,
+                                outcome,
+                                outcome.name(),
+                            );
+                        }
+                    });
+                ui.label(format!(
+                    "Capturas: {} de {} intentos ({:.0} %)",
+                    capture_statistics.captures,
+                    capture_statistics.attempts,
+                    capture_statistics.success_rate() * 100.0
+                ));
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.refuge_range,
+                        SimulationConfiguration::REFUGE_RANGE_RANGE,
+                    )
+                    .text("Alcance de los refugios"),
+                );
+                ui.checkbox(&mut simulation_config.pack_hunting, "Caza en manada");
+                ui.collapsing("Comportamiento de los depredadores", |ui| {
+                    let behaviour = &mut simulation_config.predator_behaviour;
+                    for (value, range, text) in [
+                        (
+                            &mut behaviour.detection_radius,
+                            PredatorBehaviourConfiguration::DISTANCE_RANGE,
+                            "Radio de detección",
+                        ),
+                        (
+                            &mut behaviour.attack_distance,
+                            PredatorBehaviourConfiguration::DISTANCE_RANGE,
+                            "Distancia de ataque",
+                        ),
+                        (
+                            &mut behaviour.stalk_time,
+                            PredatorBehaviourConfiguration::DURATION_RANGE,
+                            "Duración máxima del acecho (s)",
+                        ),
+                        (
+                            &mut behaviour.attack_time,
+                            PredatorBehaviourConfiguration::DURATION_RANGE,
+                            "Duración máxima del ataque (s)",
+                        ),
+                        (
+                            &mut behaviour.rest_time,
+                            PredatorBehaviourConfiguration::DURATION_RANGE,
+                            "Duración del descanso (s)",
+                        ),
+                        (
+                            &mut behaviour.stalk_intensity,
+                            0.0..=1.0,
+                            "Intensidad del acecho",
+                        ),
+                        (
+                            &mut behaviour.sprint_multiplier,
+                            PredatorBehaviourConfiguration::SPRINT_MULTIPLIER_RANGE,
+                            "Multiplicador de velocidad en ataque",
+                        ),
+                    ] {
+                        ui.add(egui::Slider::new(value, range).text(text));
+                    }
+                    egui::ComboBox::from_label("Estrategia de caza")
+                        .selected_text(behaviour.targeting.name())
+                        .show_ui(ui, |ui| {
+                            for strategy in TargetingStrategy::ALL {
+                                ui.selectable_value(
+                                    &mut behaviour.targeting,
+                                    strategy,
+                                    strategy.name(),
+                                );
+                            }
+                        });
+                    if matches!(
+                        behaviour.targeting,
+                        TargetingStrategy::MostIsolated | TargetingStrategy::Confusion
+                    ) {
+                        ui.add(
+                            egui::Slider::new(
+                                &mut behaviour.isolation_radius,
+                                PredatorBehaviourConfiguration::DISTANCE_RANGE,
+                            )
+                            .text("Radio de aglomeración"),
+                        );
+                    }
+                    if behaviour.targeting == TargetingStrategy::Confusion {
+                        ui.add(
+                            egui::Slider::new(
+                                &mut behaviour.confusion_strength,
+                                PredatorBehaviourConfiguration::CONFUSION_STRENGTH_RANGE,
+                            )
+                            .text("Intensidad de la confusión"),
+                        );
+                    }
+                    for (entity, state) in &predator_states {
+                        ui.label(format!("Depredador {entity}: {}", state.name()));
+                    }
+                });
+            }
+            egui::ComboBox::from_label("Bordes del mundo")
+                .selected_text(simulation_config.boundary_mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in BoundaryMode::ALL {
+                        ui.selectable_value(
+                            &mut simulation_config.boundary_mode,
+                            mode,
+                            mode.name(),
+                        );
+                    }
+                });
+            if simulation_config.boundary_mode == BoundaryMode::SteerBack {
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.boundary_margin,
+                        SimulationConfiguration::BOUNDARY_MARGIN_RANGE,
+                    )
+                    .text("Margen"),
+                );
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.boundary_turn_force,
+                        SimulationConfiguration::BOUNDARY_TURN_FORCE_RANGE,
+                    )
+                    .text("Fuerza de giro"),
+                );
+            }
+            egui::ComboBox::from_label("Boids fuera de la rejilla")
+                .selected_text(simulation_config.out_of_grid_policy.name())
+                .show_ui(ui, |ui| {
+                    for policy in OutOfGridPolicy::ALL {
+                        ui.selectable_value(
+                            &mut simulation_config.out_of_grid_policy,
+                            policy,
+                            policy.name(),
+                        );
+                    }
+                });
+            egui::ComboBox::from_label("Modelo de dirección")
+                .selected_text(simulation_config.steering_model.name())
+                .show_ui(ui, |ui| {
+                    for model in SteeringModel::ALL {
+                        ui.selectable_value(
+                            &mut simulation_config.steering_model,
+                            model,
+                            model.name(),
+                        );
+                    }
+                });
+            egui::ComboBox::from_label("Integrador")
+                .selected_text(simulation_config.integrator.name())
+                .show_ui(ui, |ui| {
+                    for integrator in Integrator::ALL {
+                        ui.selectable_value(
+                            &mut simulation_config.integrator,
+                            integrator,
+                            integrator.name(),
+                        );
+                    }
+                });
+            ui.add(
+                egui::Slider::new(
+                    &mut simulation_config.substeps,
+                    SimulationConfiguration::SUBSTEPS_RANGE,
+                )
+                .text("Subpasos por tick"),
+            );
+            egui::ComboBox::from_label("Vecindario")
+                .selected_text(simulation_config.neighbourhood_mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in NeighbourhoodMode::ALL {
+                        ui.selectable_value(
+                            &mut simulation_config.neighbourhood_mode,
+                            mode,
+                            mode.name(),
+                        );
+                    }
+                });
+            if simulation_config.neighbourhood_mode != NeighbourhoodMode::Metric {
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.topological_neighbours,
+                        SimulationConfiguration::TOPOLOGICAL_NEIGHBOURS_RANGE,
+                    )
+                    .text("Vecinos topológicos"),
+                );
+            }
+            egui::ComboBox::from_label("Tamaño de celda")
+                .selected_text(simulation_config.cell_size_policy.name())
+                .show_ui(ui, |ui| {
+                    for policy in CellSizePolicy::ALL {
+                        ui.selectable_value(
+                            &mut simulation_config.cell_size_policy,
+                            policy,
+                            policy.name(),
+                        );
+                    }
+                });
+            if simulation_config.cell_size_policy == CellSizePolicy::Fixed {
+                ui.add(
+                    egui::Slider::new(
+                        &mut simulation_config.grid_cell_size,
+                        SimulationConfiguration::GRID_CELL_SIZE_RANGE,
+                    )
+                    .text("Lado de la celda"),
+                );
+            }
+            let mut spatial_index_kind = world.resource::<BoidSpatialIndex>().kind();
+            egui::ComboBox::from_label("Índice espacial")
+                .selected_text(spatial_index_kind.name())
+                .show_ui(ui, |ui| {
+                    for kind in SpatialIndexKind::ALL {
+                        ui.selectable_value(&mut spatial_index_kind, kind, kind.name());
+                    }
+                });
+            if spatial_index_kind != world.resource::<BoidSpatialIndex>().kind() {
+                world
+                    .resource_mut::<BoidSpatialIndex>()
+                    .set_kind(spatial_index_kind);
+            }
+            let mut follow_window = world.resource::<WorldBounds>().follow_window;
+            if ui
+                .checkbox(&mut follow_window, "Ajustar el mundo a la ventana")
+                .changed()
+            {
+                world.resource_mut::<WorldBounds>().follow_window = follow_window;
             }
             if ui.button("Reiniciar simulación").clicked() {
                 world
@@ -135,4 +453,91 @@ pub fn inspector_ui(world: &mut World) {
             ui_for_entities_filtered(world, ui, true, &Filter::<With<ForceField>>::all());
         });
     });
+
+    egui::Window::new("Obstáculos").show(egui_context.get_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui_for_entities_filtered(world, ui, true, &Filter::<With<Obstacle>>::all());
+        });
+    });
+
+    egui::Window::new("Refugios").show(egui_context.get_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui_for_entities_filtered(world, ui, true, &Filter::<With<Refuge>>::all());
+        });
+    });
+
+    egui::Window::new("Manadas").show(egui_context.get_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let mut packs = world.resource_mut::<PackConfiguration>();
+            for settings in packs.packs_mut() {
+                ui.collapsing(settings.name.clone(), |ui| {
+                    ui.checkbox(&mut settings.share_targets, "Compartir objetivo");
+                    ui.checkbox(&mut settings.encircle, "Rodear a la presa");
+                    if settings.encircle {
+                        ui.add(
+                            egui::Slider::new(
+                                &mut settings.encircle_radius,
+                                PackSettings::ENCIRCLE_RADIUS_RANGE,
+                            )
+                            .text("Radio del cerco"),
+                        );
+                    }
+                    ui.checkbox(&mut settings.coordinated_attack, "Ataque coordinado");
+                    if settings.coordinated_attack {
+                        ui.add(
+                            egui::Slider::new(&mut settings.attack_quorum, 0.0..=1.0)
+                                .text("Fracción lista para atacar"),
+                        );
+                    }
+                });
+            }
+        });
+    });
+
+    egui::Window::new("Especies").show(egui_context.get_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let mut interactions = world.resource_mut::<InteractionMatrix>();
+            let species = interactions
+                .species()
+                .map(|(species, definition)| (species, definition.name.clone()))
+                .collect::<Vec<_>>();
+            for (observer, observer_name) in &species {
+                for (other, other_name) in &species {
+                    ui.collapsing(format!("{observer_name} ante {other_name}"), |ui| {
+                        let interaction = interactions.interaction_mut(*observer, *other);
+                        for (value, text) in [
+                            (&mut interaction.cohesion, "Cohesión"),
+                            (&mut interaction.alignment, "Alineamiento"),
+                            (&mut interaction.separation, "Separación"),
+                            (&mut interaction.flee, "Huida"),
+                            (&mut interaction.hunt, "Caza"),
+                        ] {
+                            ui.add(egui::Slider::new(value, SpeciesInteraction::RANGE).text(text));
+                        }
+                    });
+                }
+            }
+        });
+    });
+}
+
+// Unticked parametres follow the global configuration
+fn override_slider(
+    ui: &mut egui::Ui,
+    value: &mut Option<f32>,
+    global: f32,
+    range: RangeInclusive<f32>,
+    text: &str,
+) {
+    ui.horizontal(|ui| {
+        let mut overridden = value.is_some();
+        ui.checkbox(&mut overridden, "");
+        if overridden {
+            let value = value.get_or_insert(global);
+            ui.add(egui::Slider::new(value, range).text(text));
+        } else {
+            *value = None;
+            ui.label(format!("{text}: {global:.2} (global)"));
+        }
+    });
 }
@@ -7,7 +7,7 @@ pub mod states;
 pub mod systems;
 
 use self::{asset_related::*, boid_simulation::*, inspector::*, states::*, systems::*};
-use bevy::{app::plugin_group, prelude::*};
+use bevy::{app::plugin_group, prelude::*, state::app::StatesPlugin};
 
 plugin_group! {
     pub struct BevyFlockSimulationEnginePlugins {
@@ -31,3 +31,17 @@ impl Plugin for BogStandardPlugin {
             .add_systems(OnEnter(AppState::Finished), exit);
     }
 }
+
+#[derive(Default)]
+pub struct HeadlessFlockPlugin;
+
+impl Plugin for HeadlessFlockPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<StatesPlugin>() {
+            app.add_plugins(StatesPlugin);
+        }
+        app.insert_state(AppState::Running)
+            .insert_resource(Time::<Fixed>::from_hz(60.0))
+            .add_plugins(BoidSimulationPlugin::headless());
+    }
+}
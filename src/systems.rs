@@ -14,6 +14,15 @@ pub fn common_input(
     }
 }
 
-pub fn exit(mut commands: Commands, window: Single<Entity, With<Window>>) {
-    commands.entity(window.entity()).despawn();
+pub fn exit(
+    mut commands: Commands,
+    windows: Query<Entity, With<Window>>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if windows.is_empty() {
+        app_exit.write(AppExit::Success);
+    }
+    for window in windows {
+        commands.entity(window).despawn();
+    }
 }